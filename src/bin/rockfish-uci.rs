@@ -0,0 +1,19 @@
+use rockfish::{bitboard, eval_params, uci};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match eval_params::apply_args(&args) {
+        Ok(rest) if rest.is_empty() => {}
+        Ok(rest) => {
+            eprintln!("unknown arguments: {}", rest.join(" "));
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    bitboard::init();
+    uci::run();
+}
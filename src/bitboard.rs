@@ -0,0 +1,307 @@
+use std::sync::OnceLock;
+
+// Squares are numbered the same way as the board array is laid out:
+// index = row * 8 + col, so a8 = 0, h8 = 7, a1 = 56 and h1 = 63.
+pub type Bitboard = u64;
+
+#[inline(always)]
+pub fn square_index(square: (u8, u8)) -> usize {
+    square.0 as usize * 8 + square.1 as usize
+}
+
+#[inline(always)]
+pub fn square_coords(index: usize) -> (u8, u8) {
+    ((index / 8) as u8, (index % 8) as u8)
+}
+
+#[inline(always)]
+pub fn bit(index: usize) -> Bitboard {
+    1u64 << index
+}
+
+/// Every square of a column, 0 = the a-file.
+#[inline(always)]
+pub fn file_mask(col: usize) -> Bitboard {
+    0x0101_0101_0101_0101 << col
+}
+
+/// Every square of a row, 0 = the eighth rank.
+#[inline(always)]
+pub fn row_mask(row: usize) -> Bitboard {
+    0xFF << (row * 8)
+}
+
+/// The columns either side of `col`.
+#[inline(always)]
+pub fn adjacent_files(col: usize) -> Bitboard {
+    let left = if col > 0 { file_mask(col - 1) } else { 0 };
+    let right = if col < 7 { file_mask(col + 1) } else { 0 };
+    left | right
+}
+
+/// Yields the index of every set bit, lowest first.
+pub struct BitIter(pub Bitboard);
+
+impl Iterator for BitIter {
+    type Item = usize;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None;
+        }
+        let index = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(index)
+    }
+}
+
+// =========================
+// ATTACK TABLES
+// =========================
+
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const KNIGHT_JUMPS: [(i8, i8); 8] = [(-2, -1), (-2, 1), (-1, -2), (1, -2), (2, -1), (2, 1), (1, 2), (-1, 2)];
+const KING_STEPS: [(i8, i8); 8] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+
+#[derive(Clone, Copy, Default)]
+struct Magic {
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    #[inline(always)]
+    fn index(&self, occupied: Bitboard) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+struct AttackTables {
+    knight: [Bitboard; 64],
+    king: [Bitboard; 64],
+    // [color][square], color 0 = White (captures towards row 0), 1 = Black
+    pawn: [[Bitboard; 64]; 2],
+    rook_magics: [Magic; 64],
+    bishop_magics: [Magic; 64],
+    slider_attacks: Vec<Bitboard>,
+    between: Vec<Bitboard>,
+}
+
+fn tables() -> &'static AttackTables {
+    static TABLES: OnceLock<AttackTables> = OnceLock::new();
+    TABLES.get_or_init(AttackTables::new)
+}
+
+fn offset_square(index: usize, step: (i8, i8)) -> Option<usize> {
+    let row = (index / 8) as i8 + step.0;
+    let col = (index % 8) as i8 + step.1;
+    if (0..8).contains(&row) && (0..8).contains(&col) {
+        Some(row as usize * 8 + col as usize)
+    } else {
+        None
+    }
+}
+
+fn step_attacks(index: usize, steps: &[(i8, i8)]) -> Bitboard {
+    steps
+        .iter()
+        .filter_map(|s| offset_square(index, *s))
+        .fold(0, |acc, sq| acc | bit(sq))
+}
+
+// Same walk as get_legal_long_ray_moves: go until the edge or the first blocker (inclusive).
+fn ray_attacks(index: usize, occupied: Bitboard, directions: &[(i8, i8); 4]) -> Bitboard {
+    let mut attacks = 0;
+    for dir in directions {
+        let mut current = index;
+        while let Some(next) = offset_square(current, *dir) {
+            attacks |= bit(next);
+            if occupied & bit(next) != 0 {
+                break;
+            }
+            current = next;
+        }
+    }
+    attacks
+}
+
+// The squares whose occupancy matters for a slider: every ray square except the last one,
+// since a blocker on the edge doesn't change anything.
+fn relevant_mask(index: usize, directions: &[(i8, i8); 4]) -> Bitboard {
+    let mut mask = 0;
+    for dir in directions {
+        let mut current = index;
+        while let Some(next) = offset_square(current, *dir) {
+            if offset_square(next, *dir).is_none() {
+                break;
+            }
+            mask |= bit(next);
+            current = next;
+        }
+    }
+    mask
+}
+
+struct Prng(u64);
+
+impl Prng {
+    fn next(&mut self) -> u64 {
+        // xorshift64*
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(2685821657736338717)
+    }
+
+    // Magics with few set bits are found much faster.
+    fn sparse(&mut self) -> u64 {
+        self.next() & self.next() & self.next()
+    }
+}
+
+/// Searches a magic multiplier for every square and appends the attack sets to `attacks`.
+/// The seed is fixed, so the same tables come out on every run.
+fn find_magics(directions: &[(i8, i8); 4], attacks: &mut Vec<Bitboard>, rng: &mut Prng) -> [Magic; 64] {
+    let mut magics = [Magic::default(); 64];
+
+    let mut occupancies = Vec::with_capacity(4096);
+    let mut references = Vec::with_capacity(4096);
+    let mut epoch = vec![0u32; 4096];
+    let mut attempt = 0u32;
+
+    for (index, magic) in magics.iter_mut().enumerate() {
+        let mask = relevant_mask(index, directions);
+        let bits = mask.count_ones();
+
+        // Enumerate every subset of the mask (Carry-Rippler trick).
+        occupancies.clear();
+        references.clear();
+        let mut subset: Bitboard = 0;
+        loop {
+            occupancies.push(subset);
+            references.push(ray_attacks(index, subset, directions));
+            subset = subset.wrapping_sub(mask) & mask;
+            if subset == 0 {
+                break;
+            }
+        }
+
+        magic.mask = mask;
+        magic.shift = 64 - bits;
+        magic.offset = attacks.len();
+        attacks.resize(attacks.len() + (1 << bits), 0);
+
+        'search: loop {
+            magic.magic = rng.sparse();
+            if (mask.wrapping_mul(magic.magic) >> 56).count_ones() < 6 {
+                continue;
+            }
+
+            attempt += 1;
+            for (occupied, reference) in occupancies.iter().zip(references.iter()) {
+                let slot = magic.index(*occupied);
+                let local = slot - magic.offset;
+                if epoch[local] != attempt {
+                    epoch[local] = attempt;
+                    attacks[slot] = *reference;
+                } else if attacks[slot] != *reference {
+                    continue 'search;
+                }
+            }
+            break;
+        }
+    }
+
+    magics
+}
+
+impl AttackTables {
+    fn new() -> Self {
+        let mut knight = [0; 64];
+        let mut king = [0; 64];
+        let mut pawn = [[0; 64]; 2];
+
+        for index in 0..64 {
+            knight[index] = step_attacks(index, &KNIGHT_JUMPS);
+            king[index] = step_attacks(index, &KING_STEPS);
+            pawn[0][index] = step_attacks(index, &[(-1, -1), (-1, 1)]);
+            pawn[1][index] = step_attacks(index, &[(1, -1), (1, 1)]);
+        }
+
+        let mut rng = Prng(0x9E37_79B9_7F4A_7C15);
+        let mut slider_attacks = Vec::new();
+        let rook_magics = find_magics(&ROOK_DIRECTIONS, &mut slider_attacks, &mut rng);
+        let bishop_magics = find_magics(&BISHOP_DIRECTIONS, &mut slider_attacks, &mut rng);
+
+        let mut between = vec![0; 64 * 64];
+        for from in 0..64 {
+            for dir in ROOK_DIRECTIONS.iter().chain(BISHOP_DIRECTIONS.iter()) {
+                let mut squares = 0;
+                let mut current = from;
+                while let Some(next) = offset_square(current, *dir) {
+                    between[from * 64 + next] = squares;
+                    squares |= bit(next);
+                    current = next;
+                }
+            }
+        }
+
+        Self {
+            knight,
+            king,
+            pawn,
+            rook_magics,
+            bishop_magics,
+            slider_attacks,
+            between,
+        }
+    }
+}
+
+/// Builds the attack tables up front so the first search doesn't pay for it.
+pub fn init() {
+    tables();
+}
+
+#[inline(always)]
+pub fn knight_attacks(index: usize) -> Bitboard {
+    tables().knight[index]
+}
+
+#[inline(always)]
+pub fn king_attacks(index: usize) -> Bitboard {
+    tables().king[index]
+}
+
+/// Squares a pawn of `color_index` (0 = White, 1 = Black) on `index` attacks.
+#[inline(always)]
+pub fn pawn_attacks(color_index: usize, index: usize) -> Bitboard {
+    tables().pawn[color_index][index]
+}
+
+#[inline(always)]
+pub fn rook_attacks(index: usize, occupied: Bitboard) -> Bitboard {
+    let t = tables();
+    t.slider_attacks[t.rook_magics[index].index(occupied)]
+}
+
+#[inline(always)]
+pub fn bishop_attacks(index: usize, occupied: Bitboard) -> Bitboard {
+    let t = tables();
+    t.slider_attacks[t.bishop_magics[index].index(occupied)]
+}
+
+#[inline(always)]
+pub fn queen_attacks(index: usize, occupied: Bitboard) -> Bitboard {
+    rook_attacks(index, occupied) | bishop_attacks(index, occupied)
+}
+
+/// Squares strictly between two squares on the same rank, file or diagonal (empty otherwise).
+#[inline(always)]
+pub fn between(a: usize, b: usize) -> Bitboard {
+    tables().between[a * 64 + b]
+}
@@ -1,7 +1,7 @@
 use crate::pieces_logic;
 
 
-fn get_char_symbol_for_symbol_enum(symbol: pieces_logic::Symbol, side: bool) -> char {
+pub fn get_char_symbol_for_symbol_enum(symbol: pieces_logic::Symbol, side: bool) -> char {
     let mut output = match symbol {
         pieces_logic::Symbol::King => 'k',
         pieces_logic::Symbol::Queen => 'q',
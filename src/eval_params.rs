@@ -0,0 +1,408 @@
+//! Every weight of the handcrafted evaluation in one set, which can be saved to and loaded from
+//! a JSON or TOML file, so weights can be tuned and shipped without recompiling.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::pieces_logic::{Score, Symbol};
+
+/// Indexed as [row][col], row 0 = rank 8 as White sees the board. Black uses it mirrored.
+pub type SquareTable = [[i64; 8]; 8];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceSquareTables {
+    pub mg: SquareTable,
+    pub eg: SquareTable,
+}
+
+/// Worth of each square a piece can go to beyond what it typically has, so a piece counts as
+/// neither good nor bad at about average mobility.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mobility {
+    pub per_square: Score,
+    pub typical: i64,
+}
+
+/// The weights of the handcrafted evaluation. Every `Score` is a `[midgame, endgame]` pair;
+/// fields a file leaves out keep their defaults.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvalParams {
+    // Minor pieces lose a little value as the board empties, rooks and queens gain some.
+    pub pawn_value: Score,
+    pub knight_value: Score,
+    pub bishop_value: Score,
+    pub rook_value: Score,
+    pub queen_value: Score,
+
+    pub pawn_tables: PieceSquareTables,
+    pub knight_tables: PieceSquareTables,
+    pub bishop_tables: PieceSquareTables,
+    pub rook_tables: PieceSquareTables,
+    pub queen_tables: PieceSquareTables,
+    /// Behind its pawns in a corner while queens and rooks are around, in the centre once they are gone.
+    pub king_tables: PieceSquareTables,
+
+    /// By how far the pawn has come: 1 = still on its starting rank, 6 = one step from promoting.
+    pub passed_pawn: [Score; 8],
+    pub connected_pawn: [Score; 8],
+    /// Per extra pawn on the same file.
+    pub doubled_pawn: Score,
+    /// No friendly pawn on either neighbouring file.
+    pub isolated_pawn: Score,
+    /// Left behind by its neighbours, with its way forward guarded by an enemy pawn.
+    pub backward_pawn: Score,
+    /// A passed pawn with a piece standing right in front of it gets its bonus divided by this.
+    pub blocked_passed_pawn_divisor: i64,
+
+    pub knight_mobility: Mobility,
+    pub bishop_mobility: Mobility,
+    pub rook_mobility: Mobility,
+    pub queen_mobility: Mobility,
+
+    /// Own pawns on the king's and the neighbouring files, right in front of it or one further.
+    pub shield_pawn: [Score; 2],
+    /// A file next to the king without own pawns...
+    pub semi_open_king_file: Score,
+    /// ...and on top of that without any pawns at all.
+    pub open_king_file: Score,
+    /// What each attack on a square around the king adds to the danger, by attacker.
+    pub minor_king_attack: i64,
+    pub rook_king_attack: i64,
+    pub queen_king_attack: i64,
+    /// Percent of the danger that counts, by how many pieces take part: one alone can't do much.
+    pub king_attackers_scale: [i64; 8],
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        Self {
+            pawn_value: Score::new(100, 120),
+            knight_value: Score::new(320, 300),
+            bishop_value: Score::new(330, 320),
+            rook_value: Score::new(500, 530),
+            queen_value: Score::new(900, 950),
+
+            pawn_tables: PieceSquareTables {
+                mg: [
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                    [ 50, 50, 50, 50, 50, 50, 50, 50 ],
+                    [ 10, 10, 20, 30, 30, 20, 10, 10 ],
+                    [  5,  5, 10, 25, 25, 10,  5,  5 ],
+                    [  0,  0,  0, 20, 20,  0,  0,  0 ],
+                    [  5, -5,-10,  0,  0,-10, -5,  5 ],
+                    [  5, 10, 10,-20,-20, 10, 10,  5 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                ],
+                eg: [
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                    [ 80, 80, 80, 80, 80, 80, 80, 80 ],
+                    [ 50, 50, 50, 50, 50, 50, 50, 50 ],
+                    [ 30, 30, 30, 30, 30, 30, 30, 30 ],
+                    [ 15, 15, 15, 15, 15, 15, 15, 15 ],
+                    [  5,  5,  5,  5,  5,  5,  5,  5 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                ],
+            },
+            knight_tables: PieceSquareTables {
+                mg: [
+                    [-50,-40,-30,-30,-30,-30,-40,-50],
+                    [-40,-20,  0,  5,  5,  0,-20,-40],
+                    [-30,  5, 10, 15, 15, 10,  5,-30],
+                    [-30,  0, 15, 20, 20, 15,  0,-30],
+                    [-30,  5, 15, 20, 20, 15,  5,-30],
+                    [-30,  0, 10, 15, 15, 10,  0,-30],
+                    [-40,-20,  0,  0,  0,  0,-20,-40],
+                    [-50,-40,-30,-30,-30,-30,-40,-50],
+                ],
+                eg: [
+                    [-50,-40,-30,-30,-30,-30,-40,-50],
+                    [-40,-20,  0,  0,  0,  0,-20,-40],
+                    [-30,  0, 10, 15, 15, 10,  0,-30],
+                    [-30,  5, 15, 20, 20, 15,  5,-30],
+                    [-30,  5, 15, 20, 20, 15,  5,-30],
+                    [-30,  0, 10, 15, 15, 10,  0,-30],
+                    [-40,-20,  0,  0,  0,  0,-20,-40],
+                    [-50,-40,-30,-30,-30,-30,-40,-50],
+                ],
+            },
+            bishop_tables: PieceSquareTables {
+                mg: [
+                    [-20,-10,-10,-10,-10,-10,-10,-20],
+                    [-10,  0,  0,  0,  0,  0,  0,-10],
+                    [-10,  0,  5, 10, 10,  5,  0,-10],
+                    [-10,  5,  5, 10, 10,  5,  5,-10],
+                    [-10,  0, 10, 10, 10, 10,  0,-10],
+                    [-10, 10, 10, 10, 10, 10, 10,-10],
+                    [-10,  5,  0,  0,  0,  0,  5,-10],
+                    [-20,-10,-10,-10,-10,-10,-10,-20],
+                ],
+                eg: [
+                    [-20,-10,-10,-10,-10,-10,-10,-20],
+                    [-10,  0,  0,  0,  0,  0,  0,-10],
+                    [-10,  0,  5, 10, 10,  5,  0,-10],
+                    [-10,  0, 10, 15, 15, 10,  0,-10],
+                    [-10,  0, 10, 15, 15, 10,  0,-10],
+                    [-10,  0,  5, 10, 10,  5,  0,-10],
+                    [-10,  0,  0,  0,  0,  0,  0,-10],
+                    [-20,-10,-10,-10,-10,-10,-10,-20],
+                ],
+            },
+            rook_tables: PieceSquareTables {
+                mg: [
+                    [  0,  0,  0,  5,  5,  0,  0,  0 ],
+                    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
+                    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
+                    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
+                    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
+                    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
+                    [  5, 10, 10, 10, 10, 10, 10,  5 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                ],
+                eg: [
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                    [ 10, 10, 10, 10, 10, 10, 10, 10 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                    [  0,  0,  0,  0,  0,  0,  0,  0 ],
+                ],
+            },
+            queen_tables: PieceSquareTables {
+                mg: [
+                    [-20,-10,-10, -5, -5,-10,-10,-20],
+                    [-10,  0,  0,  0,  0,  0,  0,-10],
+                    [-10,  0,  5,  5,  5,  5,  0,-10],
+                    [ -5,  0,  5,  5,  5,  5,  0, -5],
+                    [  0,  0,  5,  5,  5,  5,  0, -5],
+                    [-10,  5,  5,  5,  5,  5,  0,-10],
+                    [-10,  0,  5,  0,  0,  0,  0,-10],
+                    [-20,-10,-10, -5, -5,-10,-10,-20],
+                ],
+                eg: [
+                    [-20,-10,-10, -5, -5,-10,-10,-20],
+                    [-10,  0,  5,  5,  5,  5,  0,-10],
+                    [-10,  5, 10, 10, 10, 10,  5,-10],
+                    [ -5,  5, 10, 15, 15, 10,  5, -5],
+                    [ -5,  5, 10, 15, 15, 10,  5, -5],
+                    [-10,  5, 10, 10, 10, 10,  5,-10],
+                    [-10,  0,  5,  5,  5,  5,  0,-10],
+                    [-20,-10,-10, -5, -5,-10,-10,-20],
+                ],
+            },
+            king_tables: PieceSquareTables {
+                mg: [
+                    [-30,-40,-40,-50,-50,-40,-40,-30],
+                    [-30,-40,-40,-50,-50,-40,-40,-30],
+                    [-30,-40,-40,-50,-50,-40,-40,-30],
+                    [-30,-40,-40,-50,-50,-40,-40,-30],
+                    [-20,-30,-30,-40,-40,-30,-30,-20],
+                    [-10,-20,-20,-20,-20,-20,-20,-10],
+                    [ 20, 20,  0,  0,  0,  0, 20, 20],
+                    [ 20, 30, 10,  0,  0, 10, 30, 20],
+                ],
+                eg: [
+                    [-50,-40,-30,-20,-20,-30,-40,-50],
+                    [-30,-20,-10,  0,  0,-10,-20,-30],
+                    [-30,-10, 20, 30, 30, 20,-10,-30],
+                    [-30,-10, 30, 40, 40, 30,-10,-30],
+                    [-30,-10, 30, 40, 40, 30,-10,-30],
+                    [-30,-10, 20, 30, 30, 20,-10,-30],
+                    [-30,-30,  0,  0,  0,  0,-30,-30],
+                    [-50,-30,-30,-30,-30,-30,-30,-50],
+                ],
+            },
+
+            passed_pawn: [
+                Score::new(0, 0),
+                Score::new(5, 10),
+                Score::new(10, 15),
+                Score::new(15, 30),
+                Score::new(30, 55),
+                Score::new(50, 90),
+                Score::new(80, 140),
+                Score::new(0, 0),
+            ],
+            connected_pawn: [
+                Score::new(0, 0),
+                Score::new(5, 0),
+                Score::new(8, 3),
+                Score::new(12, 8),
+                Score::new(20, 15),
+                Score::new(35, 30),
+                Score::new(60, 50),
+                Score::new(0, 0),
+            ],
+            doubled_pawn: Score::new(-10, -25),
+            isolated_pawn: Score::new(-15, -15),
+            backward_pawn: Score::new(-10, -10),
+            blocked_passed_pawn_divisor: 2,
+
+            knight_mobility: Mobility { per_square: Score::new(4, 4), typical: 4 },
+            bishop_mobility: Mobility { per_square: Score::new(5, 5), typical: 6 },
+            rook_mobility: Mobility { per_square: Score::new(2, 4), typical: 7 },
+            queen_mobility: Mobility { per_square: Score::new(1, 2), typical: 13 },
+
+            shield_pawn: [Score::new(15, 0), Score::new(8, 0)],
+            semi_open_king_file: Score::new(-20, 0),
+            open_king_file: Score::new(-10, 0),
+            minor_king_attack: 20,
+            rook_king_attack: 40,
+            queen_king_attack: 80,
+            king_attackers_scale: [0, 0, 50, 75, 88, 94, 97, 100],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParamsError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    TomlRead(toml::de::Error),
+    TomlWrite(toml::ser::Error),
+    UnknownFormat(String),
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamsError::Io(e) => write!(f, "{}", e),
+            ParamsError::Json(e) => write!(f, "invalid JSON: {}", e),
+            ParamsError::TomlRead(e) => write!(f, "invalid TOML: {}", e),
+            ParamsError::TomlWrite(e) => write!(f, "can't write TOML: {}", e),
+            ParamsError::UnknownFormat(path) => write!(f, "{} should end in .json or .toml", path),
+        }
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+// The file format goes by the extension.
+fn is_toml(path: &Path) -> Result<bool, ParamsError> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => Ok(false),
+        Some("toml") => Ok(true),
+        _ => Err(ParamsError::UnknownFormat(path.display().to_string())),
+    }
+}
+
+impl EvalParams {
+    pub fn from_json(text: &str) -> Result<Self, ParamsError> {
+        serde_json::from_str(text).map_err(ParamsError::Json)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("the weights are plain numbers")
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ParamsError> {
+        toml::from_str(text).map_err(ParamsError::TomlRead)
+    }
+
+    pub fn to_toml(&self) -> Result<String, ParamsError> {
+        toml::to_string(self).map_err(ParamsError::TomlWrite)
+    }
+
+    /// Reads a `.json` or `.toml` file.
+    pub fn load(path: &Path) -> Result<Self, ParamsError> {
+        let toml = is_toml(path)?;
+        let text = fs::read_to_string(path).map_err(ParamsError::Io)?;
+        if toml { Self::from_toml(&text) } else { Self::from_json(&text) }
+    }
+
+    /// Writes a `.json` or `.toml` file.
+    pub fn save(&self, path: &Path) -> Result<(), ParamsError> {
+        let text = if is_toml(path)? { self.to_toml()? } else { self.to_json() };
+        fs::write(path, text).map_err(ParamsError::Io)
+    }
+
+    pub fn piece_value(&self, sym: Symbol) -> Score {
+        match sym {
+            Symbol::Pawn => self.pawn_value,
+            Symbol::Knight => self.knight_value,
+            Symbol::Bishop => self.bishop_value,
+            Symbol::Rook => self.rook_value,
+            Symbol::Queen => self.queen_value,
+            _ => Score::default(),
+        }
+    }
+
+    pub fn tables(&self, sym: Symbol) -> Option<&PieceSquareTables> {
+        match sym {
+            Symbol::Pawn => Some(&self.pawn_tables),
+            Symbol::Knight => Some(&self.knight_tables),
+            Symbol::Bishop => Some(&self.bishop_tables),
+            Symbol::Rook => Some(&self.rook_tables),
+            Symbol::Queen => Some(&self.queen_tables),
+            Symbol::King => Some(&self.king_tables),
+            _ => None,
+        }
+    }
+
+    pub fn mobility(&self, sym: Symbol) -> Mobility {
+        match sym {
+            Symbol::Knight => self.knight_mobility,
+            Symbol::Bishop => self.bishop_mobility,
+            Symbol::Rook => self.rook_mobility,
+            _ => self.queen_mobility,
+        }
+    }
+
+    pub fn king_attack(&self, sym: Symbol) -> i64 {
+        match sym {
+            Symbol::Knight | Symbol::Bishop => self.minor_king_attack,
+            Symbol::Rook => self.rook_king_attack,
+            Symbol::Queen => self.queen_king_attack,
+            _ => 0,
+        }
+    }
+}
+
+static PARAMS: OnceLock<EvalParams> = OnceLock::new();
+
+/// The weights every evaluation uses: the installed ones, or else the defaults.
+pub fn params() -> &'static EvalParams {
+    PARAMS.get_or_init(EvalParams::default)
+}
+
+/// Makes `params` the weights of every evaluation from now on. Only possible at startup,
+/// before the first evaluation, since scores computed with the old weights get cached.
+/// Returns false when it is too late.
+pub fn install(params: EvalParams) -> bool {
+    PARAMS.set(params).is_ok()
+}
+
+/// Handles the command line options every front end shares: `--eval-params <file>` installs
+/// the weights in the file, `--save-eval-params <file>` writes the weights in use to one.
+/// Returns the arguments left over.
+pub fn apply_args(args: &[String]) -> Result<Vec<String>, String> {
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--eval-params" => {
+                let path = args.next().ok_or("--eval-params needs a file")?;
+                let loaded = EvalParams::load(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+                if !install(loaded) {
+                    return Err("evaluation weights were already in use".to_string());
+                }
+            }
+            "--save-eval-params" => {
+                let path = args.next().ok_or("--save-eval-params needs a file")?;
+                params().save(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    Ok(rest)
+}
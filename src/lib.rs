@@ -0,0 +1,9 @@
+pub mod bitboard;
+pub mod chess_board;
+pub mod eval_params;
+pub mod perft;
+pub mod pieces_logic;
+pub mod position;
+pub mod time_manager;
+pub mod uci;
+pub mod xboard;
@@ -1,16 +1,17 @@
-mod chess_board;
-mod pieces_logic;
+use rockfish::{bitboard, chess_board, eval_params, perft, pieces_logic, position, time_manager};
 
 use eframe::egui;
 use egui::{Color32, FontId, Pos2, Rect, Sense, Vec2};
 use num_format::{Locale, ToFormattedString};
 
-use pieces_logic::{Color, Move, Piece, Promotion, Symbol};
+use pieces_logic::{Color, Engine, Evaluator, HandcraftedEvaluator, Move, Piece, Promotion, SearchInfo, Symbol};
+use position::Position;
+use time_manager::SearchLimits;
 
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     mpsc,
-    Arc,
+    Arc, Mutex,
 };
 use std::thread;
 use std::time::{Duration, Instant};
@@ -21,13 +22,17 @@ use std::time::{Duration, Instant};
 
 const SAMPLE_PERIOD: Duration = Duration::from_millis(350);
 
+// The engine stops at its depth or after this long, whichever comes first.
+const ENGINE_MOVE_TIME: Duration = Duration::from_secs(10);
+
 /* =========================
    ENGINE MESSAGES
    ========================= */
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 enum EngineMsg {
     Progress { nodes: u64, nps: u64 },
+    Iteration(SearchInfo),
     Done { best: Option<Move>, nodes: u64, nps: u64 },
 }
 
@@ -52,9 +57,9 @@ enum GameOver {
    APP
    ========================= */
 
-struct ChessApp {
-    board: [[Piece; 8]; 8],
-    white_to_move: bool,
+// Generic over the evaluation the engine plays with, so another one is a one-line change in main.
+struct ChessApp<E = HandcraftedEvaluator> {
+    position: Position,
 
     selected: Option<(u8, u8)>,
     selected_moves: Vec<Move>,
@@ -63,6 +68,8 @@ struct ChessApp {
     game_mode: GameMode,
 
     // engine
+    // Keeps its transposition table from one move to the next; lent to the engine thread.
+    engine: Arc<Mutex<Engine<E>>>,
     engine_depth: u8,
     engine_thinking: bool,
     engine_rx: Option<mpsc::Receiver<EngineMsg>>,
@@ -73,17 +80,18 @@ struct ChessApp {
     // displayed stats
     nodes_display: u64,
     nps_display: u64,
+    // Last completed iteration and the side it searched for.
+    engine_line: Option<(Color, SearchInfo)>,
 }
 
 /* =========================
    INIT
    ========================= */
 
-impl Default for ChessApp {
+impl<E: Evaluator + Default> Default for ChessApp<E> {
     fn default() -> Self {
         Self {
-            board: chess_board::initialize_chess_board(),
-            white_to_move: true,
+            position: Position::starting_position(),
 
             selected: None,
             selected_moves: Vec::new(),
@@ -91,6 +99,7 @@ impl Default for ChessApp {
             game_over: None,
             game_mode: GameMode::Standard,
 
+            engine: Arc::new(Mutex::new(Engine::with_evaluator(pieces_logic::DEFAULT_HASH_MB, E::default()))),
             engine_depth: 7,
             engine_thinking: false,
             engine_rx: None,
@@ -100,6 +109,7 @@ impl Default for ChessApp {
 
             nodes_display: 0,
             nps_display: 0,
+            engine_line: None,
         }
     }
 }
@@ -116,15 +126,24 @@ fn opponent(side: Color) -> Color {
     }
 }
 
-impl ChessApp {
+// Search score from White's point of view: pawns, or #moves to mate (negative when Black mates).
+fn format_score(info: &SearchInfo, side: Color) -> String {
+    let sign = if side == Color::White { 1 } else { -1 };
+    match info.mate_in() {
+        Some(moves) => format!("#{}", moves * sign),
+        None => format!("{:+.2}", (info.score * sign) as f64 / 100.0),
+    }
+}
+
+impl<E: Evaluator + 'static> ChessApp<E> {
     fn reset(&mut self) {
         self.stop_engine_threads();
+        self.engine.lock().unwrap_or_else(|e| e.into_inner()).new_game();
 
-        self.board = match self.game_mode {
-            GameMode::Standard => chess_board::initialize_chess_board(),
-            GameMode::PawnGalore => chess_board::initialize_pawn_galore_board(),
+        self.position = match self.game_mode {
+            GameMode::Standard => Position::starting_position(),
+            GameMode::PawnGalore => Position::from_board(chess_board::initialize_pawn_galore_board(), Color::White),
         };
-        self.white_to_move = true;
         self.selected = None;
         self.selected_moves.clear();
         self.game_over = None;
@@ -132,6 +151,7 @@ impl ChessApp {
         self.engine_thinking = false;
         self.nodes_display = 0;
         self.nps_display = 0;
+        self.engine_line = None;
     }
 
     fn engine_thread_running(&self) -> bool {
@@ -154,8 +174,7 @@ impl ChessApp {
     }
 
     fn stop_engine_threads(&mut self) {
-        // Best-effort stop: we can reliably stop the *progress* thread, but the search thread
-        // itself cannot be cancelled unless the search code checks a stop flag.
+        // Both the progress thread and the search check this flag, so they wind down quickly.
         if let Some(stop) = &self.engine_stop {
             stop.store(true, Ordering::Relaxed);
         }
@@ -170,14 +189,10 @@ impl ChessApp {
             let _ = h.join();
         }
 
-        // Join the search thread only if it's already done; otherwise keep the handle so we don't
-        // start a second search that would share the global node counter.
-        if let Some(h) = self.engine_handle.as_ref() {
-            if h.is_finished() {
-                if let Some(h) = self.engine_handle.take() {
-                    let _ = h.join();
-                }
-            }
+        // The search returns within a few nodes of seeing the flag, so a new one can start right away
+        // without two searches sharing the global node counter.
+        if let Some(h) = self.engine_handle.take() {
+            let _ = h.join();
         }
     }
 
@@ -202,13 +217,6 @@ impl ChessApp {
         })
     }
 
-    fn legal_moves_from_square(&self, from: (u8, u8), side: Color) -> Vec<Move> {
-        pieces_logic::get_all_legal_moves_for_this_turn(&self.board, side)
-            .into_iter()
-            .filter(|m| m.current_square == from)
-            .collect()
-    }
-
     fn try_player_move(&mut self, from: (u8, u8), to: (u8, u8)) -> bool {
         let Some(mut mv) = self
             .selected_moves
@@ -220,17 +228,15 @@ impl ChessApp {
         };
 
         // Autopromote to queen for UI simplicity.
-        let piece = self.board[from.0 as usize][from.1 as usize];
-        if piece.symbol == Symbol::Pawn {
-            let last = if piece.color == Color::White { 0 } else { 7 };
+        if self.position.symbol_at(from) == Symbol::Pawn {
+            let last = if self.position.color_at(from) == Color::White { 0 } else { 7 };
             if to.0 == last {
                 mv.promotion = Promotion::Queen;
             }
         }
 
-        pieces_logic::make_move(&mut self.board, &mv);
+        self.position.make_move(&mv);
 
-        self.white_to_move = false;
         self.selected = None;
         self.selected_moves.clear();
         self.update_game_over();
@@ -238,22 +244,17 @@ impl ChessApp {
     }
 
     fn update_game_over(&mut self) {
-        if pieces_logic::is_insufficient_material(&self.board) {
+        if self.position.is_insufficient_material() {
             self.game_over = Some(GameOver::InsufficientMaterial);
             return;
         }
 
-        let side = if self.white_to_move {
-            Color::White
-        } else {
-            Color::Black
-        };
-        let moves = pieces_logic::get_all_legal_moves_for_this_turn(&self.board, side);
+        let moves = self.position.legal_moves();
 
         if moves.is_empty() {
-            if pieces_logic::is_king_in_check(&self.board, side) {
+            if self.position.is_in_check() {
                 self.game_over = Some(GameOver::Checkmate {
-                    winner_white: !self.white_to_move,
+                    winner_white: self.position.side_to_move == Color::Black,
                 });
             } else {
                 self.game_over = Some(GameOver::Stalemate);
@@ -288,7 +289,7 @@ impl ChessApp {
         // join it once it finishes so we can start a fresh search later.
         self.reap_finished_engine_thread();
 
-        if self.white_to_move
+        if self.position.side_to_move == Color::White
             || self.game_over.is_some()
             || self.engine_thinking
             || self.engine_thread_running()
@@ -300,7 +301,7 @@ impl ChessApp {
         self.nodes_display = 0;
         self.nps_display = 0;
 
-        let board_copy = self.board;
+        let position = self.position;
         let engine_depth = self.engine_depth;
 
         let (tx, rx) = mpsc::channel::<EngineMsg>();
@@ -343,12 +344,10 @@ impl ChessApp {
         }));
 
         let stop_search = stop.clone();
+        let engine = Arc::clone(&self.engine);
 
-        // Engine thread: TT+Zobrist negamax search (depth-limited).
-        // This requires these to exist in pieces_logic.rs:
-        //   - nodes_reset()
-        //   - nodes_get()
-        //   - get_best_move_negamax_tt(&board, depth, side) -> Move
+        // Engine thread: runs Engine::search on the shared engine, limited by depth and
+        // ENGINE_MOVE_TIME, and reports its progress and best move through tx.
         self.engine_handle = Some(thread::spawn(move || {
             if stop_search.load(Ordering::Relaxed) {
                 let _ = tx.send(EngineMsg::Done {
@@ -361,7 +360,7 @@ impl ChessApp {
 
             pieces_logic::nodes_reset();
 
-            let legal = pieces_logic::get_all_legal_moves_for_this_turn(&board_copy, Color::Black);
+            let legal = position.legal_moves();
 
             if stop_search.load(Ordering::Relaxed) {
                 let nodes = pieces_logic::nodes_get();
@@ -386,7 +385,24 @@ impl ChessApp {
             let start = Instant::now();
 
             // Best-move search (internally TT+Zobrist + move ordering inside negamax)
-            let best_move = pieces_logic::get_best_move_iterative_tt(&board_copy, engine_depth, Color::Black);
+            let limits = SearchLimits {
+                movetime: Some(ENGINE_MOVE_TIME),
+                ..SearchLimits::depth(engine_depth)
+            };
+            let options = pieces_logic::SearchOptions {
+                threads: thread::available_parallelism().map_or(1, |n| n.get()),
+                ..pieces_logic::SearchOptions::default()
+            };
+            let tx_info = tx.clone();
+            let best_move = engine.lock().unwrap_or_else(|e| e.into_inner()).search(
+                &position,
+                &limits,
+                &options,
+                &stop_search,
+                |info| {
+                    let _ = tx_info.send(EngineMsg::Iteration(info.clone()));
+                },
+            );
 
             let nodes = pieces_logic::nodes_get();
             let total_dt = start.elapsed().as_secs_f64();
@@ -422,6 +438,9 @@ impl ChessApp {
                     self.nodes_display = nodes;
                     self.nps_display = nps;
                 }
+                EngineMsg::Iteration(info) => {
+                    self.engine_line = Some((self.position.side_to_move, info));
+                }
                 EngineMsg::Done { best, nodes, nps } => {
                     self.nodes_display = nodes;
                     self.nps_display = nps;
@@ -443,8 +462,7 @@ impl ChessApp {
                     self.engine_thinking = false;
 
                     if let Some(mv) = best {
-                        pieces_logic::make_move(&mut self.board, &mv);
-                        self.white_to_move = true;
+                        self.position.make_move(&mv);
                         self.update_game_over();
                     }
                 }
@@ -457,7 +475,7 @@ impl ChessApp {
    APP
    ========================= */
 
-impl eframe::App for ChessApp {
+impl<E: Evaluator + 'static> eframe::App for ChessApp<E> {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         // Process engine messages first so UI reflects updates immediately.
         self.poll_engine_messages();
@@ -496,6 +514,7 @@ impl eframe::App for ChessApp {
             let painter = ui.painter_at(rect);
 
             // Board
+            let board = self.position.board();
             for r in 0..8u8 {
                 for c in 0..8u8 {
                     let sq = (r, c);
@@ -534,7 +553,7 @@ impl eframe::App for ChessApp {
                         );
                     }
 
-                    let p = self.board[r as usize][c as usize];
+                    let p = board[r as usize][c as usize];
                     if let Some(g) = Self::piece_glyph(&p) {
                         painter.text(
                             cell.center(),
@@ -553,7 +572,7 @@ impl eframe::App for ChessApp {
 
             // Click handling (player = White)
             if response.clicked()
-                && self.white_to_move
+                && self.position.side_to_move == Color::White
                 && self.game_over.is_none()
                 && !self.engine_thinking
             {
@@ -567,11 +586,10 @@ impl eframe::App for ChessApp {
                             // attempt move
                             if !self.try_player_move(from, sq) {
                                 // if failed, maybe select a different white piece
-                                let p = self.board[sq.0 as usize][sq.1 as usize];
-                                if p.color == Color::White && p.symbol != Symbol::Empty {
+                                if self.position.color_at(sq) == Color::White {
                                     self.selected = Some(sq);
                                     self.selected_moves =
-                                        self.legal_moves_from_square(sq, Color::White);
+                                        self.position.legal_moves_from_square(sq);
                                 } else {
                                     self.selected = None;
                                     self.selected_moves.clear();
@@ -579,10 +597,9 @@ impl eframe::App for ChessApp {
                             }
                         } else {
                             // select piece
-                            let p = self.board[sq.0 as usize][sq.1 as usize];
-                            if p.color == Color::White && p.symbol != Symbol::Empty {
+                            if self.position.color_at(sq) == Color::White {
                                 self.selected = Some(sq);
-                                self.selected_moves = self.legal_moves_from_square(sq, Color::White);
+                                self.selected_moves = self.position.legal_moves_from_square(sq);
                             }
                         }
                     }
@@ -599,7 +616,7 @@ impl eframe::App for ChessApp {
                 ui.vertical(|ui| {
                     ui.add_space(12.0);
 
-                    ui.heading(if self.white_to_move {
+                    ui.heading(if self.position.side_to_move == Color::White {
                         "White to move"
                     } else {
                         "Black to move"
@@ -612,6 +629,13 @@ impl eframe::App for ChessApp {
                     ui.label(format!("Nodes: {}", self.nodes_display.to_formatted_string(&Locale::en)));
                     ui.label(format!("N/s:   {}", self.nps_display.to_formatted_string(&Locale::en)));
 
+                    if let Some((side, info)) = &self.engine_line {
+                        ui.add_space(8.0);
+                        ui.label(format!("Depth: {}   Score: {}", info.depth, format_score(info, *side)));
+                        let pv: Vec<String> = info.pv.iter().map(pieces_logic::move_to_universal_chess_interface).collect();
+                        ui.label(format!("PV: {}", pv.join(" ")));
+                    }
+
                     ui.add_space(8.0);
                     if self.engine_thinking {
                         ui.label("Engine: thinking...");
@@ -685,11 +709,50 @@ impl eframe::App for ChessApp {
     }
 }
 
+// `rockfish perft <depth> [fen]` and `rockfish divide <depth> [fen]` run the move generator
+// from the command line instead of opening the window.
+fn run_perft(mode: &str, args: &[String]) {
+    let Some(depth) = args.first().and_then(|d| d.parse::<u8>().ok()) else {
+        eprintln!("usage: rockfish {} <depth> [fen]", mode);
+        return;
+    };
+
+    let fen = if args.len() > 1 { args[1..].join(" ") } else { position::STARTING_FEN.to_string() };
+    let mut position = match Position::from_fen(&fen) {
+        Ok(position) => position,
+        Err(e) => {
+            eprintln!("invalid fen: {}", e);
+            return;
+        }
+    };
+
+    if mode == "divide" {
+        perft::print_perft_divide(&mut position, depth);
+    } else {
+        perft::print_perft(&mut position, depth);
+    }
+}
+
 fn main() -> eframe::Result<()> {
+    bitboard::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args = match eval_params::apply_args(&args) {
+        Ok(rest) => rest,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(mode @ ("perft" | "divide")) = args.first().map(String::as_str) {
+        run_perft(mode, &args[1..]);
+        return Ok(());
+    }
+
     eframe::run_native(
         "Rockfish",
         eframe::NativeOptions::default(),
-        Box::new(|_| Ok(Box::new(ChessApp::default()))),
+        Box::new(|_| Ok(Box::new(ChessApp::<HandcraftedEvaluator>::default()))),
     )
 }
 
@@ -699,6 +762,8 @@ fn main() -> eframe::Result<()> {
 #[cfg(test)]
 mod tests {
     use crate::*;
+    use pieces_logic::SearchOptions;
+    use rockfish::{uci, xboard};
     
     #[test]
     fn create_empty_piece() {
@@ -1013,7 +1078,7 @@ mod tests {
         legal_moves.push(pieces_logic::Move {current_square: (6, 4), destination_square: (5, 4), castle: false, promotion: pieces_logic::Promotion::NoPromotion}); 
         legal_moves.push(pieces_logic::Move {current_square: (6, 4), destination_square: (4, 4), castle: false, promotion: pieces_logic::Promotion::NoPromotion});
 
-        let mut gen_legal_moves: Vec<pieces_logic::Move> = pieces_logic::get_legal_moves_for_pawn(&board, &(6, 4));
+        let mut gen_legal_moves: Vec<pieces_logic::Move> = pieces_logic::get_legal_moves_for_pawn(&board, &(6, 4), None);
         
         legal_moves.sort(); 
         gen_legal_moves.sort();
@@ -1023,7 +1088,7 @@ mod tests {
         legal_moves = vec![];
         legal_moves.push(pieces_logic::Move {current_square: (5, 2), destination_square: (4, 2), castle: false, promotion: pieces_logic::Promotion::NoPromotion});
         
-        gen_legal_moves = pieces_logic::get_legal_moves_for_pawn(&board, &(5, 2));
+        gen_legal_moves = pieces_logic::get_legal_moves_for_pawn(&board, &(5, 2), None);
 
         assert_eq!(legal_moves, gen_legal_moves);
 
@@ -1345,6 +1410,10 @@ mod tests {
         board[7][3] = pieces_logic::create_empty_piece(&(7, 3));
         board[7][2] = pieces_logic::create_empty_piece(&(7, 2));
 
+        // The knight on b1 still blocks the queen side rook
+        assert_eq!(0, pieces_logic::get_castling_moves(&board, Color::White).len());
+        board[7][1] = pieces_logic::create_empty_piece(&(7, 1));
+
         let mut castle_moves_white: Vec<pieces_logic::Move> = pieces_logic::get_castling_moves(&board, Color::White);
         castle_moves_white.sort();
         let castle_moves_black: Vec<pieces_logic::Move> = pieces_logic::get_castling_moves(&board, Color::Black);
@@ -1390,7 +1459,7 @@ mod tests {
         expected_board_moves.push(pieces_logic::Move { current_square: (6, 3), destination_square: (4, 3), castle: false, promotion: pieces_logic::Promotion::NoPromotion});
         expected_board_moves.push(pieces_logic::Move { current_square: (7, 4), destination_square: (6, 4), castle: false, promotion: pieces_logic::Promotion::NoPromotion});
         
-        let mut board_moves: Vec<pieces_logic::Move> = pieces_logic::get_all_legal_moves_for_this_turn(&board, Color::White);
+        let mut board_moves: Vec<pieces_logic::Move> = pieces_logic::get_all_legal_moves_for_this_turn(&board, Color::White, None);
 
         board_moves.sort();
         expected_board_moves.sort();
@@ -1407,11 +1476,11 @@ mod tests {
         pieces_logic::place_rook_on_board(&mut board, &(6, 0), Color::Black);
         pieces_logic::place_rook_on_board(&mut board, &(7, 7), Color::Black);
 
-        assert_eq!(true, pieces_logic::is_checkmate(&board, Color::White));
+        assert_eq!(true, pieces_logic::is_checkmate(&board, Color::White, None));
 
         pieces_logic::place_rook_on_board(&mut board, &(0, 5), Color::White);
 
-        assert_eq!(false, pieces_logic::is_checkmate(&board, Color::White));
+        assert_eq!(false, pieces_logic::is_checkmate(&board, Color::White, None));
 
 
     }
@@ -1425,15 +1494,15 @@ mod tests {
         pieces_logic::place_rook_on_board(&mut board, &(0, 5), Color::Black);
         pieces_logic::place_rook_on_board(&mut board, &(6, 0), Color::Black);
 
-        assert_eq!(true, pieces_logic::is_stalemate(&board, Color::White));
+        assert_eq!(true, pieces_logic::is_stalemate(&board, Color::White, None));
         
         pieces_logic::place_pawn_on_board(&mut board, &(3, 0), Color::White);
 
-        assert_eq!(false, pieces_logic::is_stalemate(&board, Color::White));
+        assert_eq!(false, pieces_logic::is_stalemate(&board, Color::White, None));
     
         pieces_logic::place_knight_on_board(&mut board, &(2, 0), Color::Black);
 
-        assert_eq!(true, pieces_logic::is_stalemate(&board, Color::White));
+        assert_eq!(true, pieces_logic::is_stalemate(&board, Color::White, None));
 
     }
 
@@ -1446,7 +1515,7 @@ mod tests {
         pieces_logic::place_pawn_on_board(&mut board, &(1, 4), Color::White);
         board[1][4].has_moved = true;
 
-        let mut pawn_moves: Vec<pieces_logic::Move> = pieces_logic::get_legal_moves_for_pawn(&board, &(1, 4));
+        let mut pawn_moves: Vec<pieces_logic::Move> = pieces_logic::get_legal_moves_for_pawn(&board, &(1, 4), None);
         
         let mut exp_pawn_moves: Vec<pieces_logic::Move> = vec![];
 
@@ -1477,7 +1546,7 @@ mod tests {
         exp_pawn_moves.push(pieces_logic::Move { current_square: (1, 4), destination_square: (0, 5), castle: false, promotion: pieces_logic::Promotion::Bishop});
         exp_pawn_moves.push(pieces_logic::Move { current_square: (1, 4), destination_square: (0, 5), castle: false, promotion: pieces_logic::Promotion::Knight});
 
-        pawn_moves = pieces_logic::get_legal_moves_for_pawn(&board, &(1, 4));
+        pawn_moves = pieces_logic::get_legal_moves_for_pawn(&board, &(1, 4), None);
 
         pawn_moves.sort();
         exp_pawn_moves.sort();
@@ -1492,7 +1561,7 @@ mod tests {
         exp_pawn_moves.push(pieces_logic::Move { current_square: (1, 4), destination_square: (0, 4), castle: false, promotion: pieces_logic::Promotion::Knight});
 
         
-        pawn_moves = pieces_logic::get_legal_moves_for_pawn(&board, &(1, 4));
+        pawn_moves = pieces_logic::get_legal_moves_for_pawn(&board, &(1, 4), None);
 
         pawn_moves.sort();
         exp_pawn_moves.sort();
@@ -1502,7 +1571,1070 @@ mod tests {
 
     #[test]
     fn en_passant() {
-        assert!(false);
+        let mut board = chess_board::create_empty_board();
+
+        pieces_logic::place_king_on_board(&mut board, &(7, 4), Color::White);
+        pieces_logic::place_king_on_board(&mut board, &(0, 4), Color::Black);
+        pieces_logic::place_pawn_on_board(&mut board, &(3, 4), Color::White);
+        board[3][4].has_moved = true;
+        pieces_logic::place_pawn_on_board(&mut board, &(1, 3), Color::Black);
+
+        // d7-d5 lands next to the e5 pawn and leaves d6 as the en passant square
+        let double_push = pieces_logic::Move { current_square: (1, 3), destination_square: (3, 3), castle: false, promotion: pieces_logic::Promotion::NoPromotion};
+        let en_passant = pieces_logic::get_en_passant_square_after_move(&board, &double_push);
+        assert_eq!(Some((2, 3)), en_passant);
+        pieces_logic::make_move(&mut board, &double_push);
+
+        let ep_capture = pieces_logic::Move { current_square: (3, 4), destination_square: (2, 3), castle: false, promotion: pieces_logic::Promotion::NoPromotion};
+
+        let mut exp_pawn_moves: Vec<pieces_logic::Move> = vec![
+            pieces_logic::Move { current_square: (3, 4), destination_square: (2, 4), castle: false, promotion: pieces_logic::Promotion::NoPromotion},
+            ep_capture,
+        ];
+        let mut pawn_moves = pieces_logic::get_legal_moves_for_pawn(&board, &(3, 4), en_passant);
+
+        pawn_moves.sort();
+        exp_pawn_moves.sort();
+        assert_eq!(exp_pawn_moves, pawn_moves);
+
+        // The right only exists on the move directly after the double push
+        assert_eq!(1, pieces_logic::get_legal_moves_for_pawn(&board, &(3, 4), None).len());
+
+        pieces_logic::make_move(&mut board, &ep_capture);
+
+        assert_eq!(Symbol::Empty, board[3][3].symbol);
+        assert_eq!(Symbol::Empty, board[3][4].symbol);
+        assert_eq!(Symbol::Pawn, board[2][3].symbol);
+        assert_eq!(Color::White, board[2][3].color);
+
+        // Hash of the position after the capture must match a hash computed from scratch
+        let z = pieces_logic::Zobrist::new();
+        let mut position = Position::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        let child_hash = position.hash_after_move(position.hash(&z), &ep_capture, &z);
+
+        position.make_move(&ep_capture);
+
+        assert_eq!(Symbol::Empty, position.symbol_at((3, 3)));
+        assert_eq!(Color::White, position.color_at((2, 3)));
+        assert_eq!(None, position.en_passant);
+        assert_eq!(child_hash, position.hash(&z));
+    }
+
+    #[test]
+    fn en_passant_horizontal_pin() {
+        let mut board = chess_board::create_empty_board();
+
+        // King, both pawns and the enemy rook all share the 5th rank; taking en passant
+        // would remove both pawns at once and expose the king.
+        pieces_logic::place_king_on_board(&mut board, &(3, 0), Color::White);
+        pieces_logic::place_pawn_on_board(&mut board, &(3, 3), Color::White);
+        board[3][3].has_moved = true;
+        pieces_logic::place_pawn_on_board(&mut board, &(3, 4), Color::Black);
+        board[3][4].has_moved = true;
+        pieces_logic::place_rook_on_board(&mut board, &(3, 7), Color::Black);
+        pieces_logic::place_king_on_board(&mut board, &(0, 7), Color::Black);
+
+        let pawn_moves = pieces_logic::get_legal_moves_for_pawn(&board, &(3, 3), Some((2, 4)));
+
+        assert_eq!(pawn_moves, [pieces_logic::Move { current_square: (3, 3), destination_square: (2, 3), castle: false, promotion: pieces_logic::Promotion::NoPromotion}]);
+    }
+
+    #[test]
+    fn fen_start_position() {
+        let position = position::Position::from_fen(position::STARTING_FEN).unwrap();
+
+        assert_eq!(chess_board::initialize_chess_board(), position.board());
+        assert_eq!(Color::White, position.side_to_move);
+        assert_eq!(None, position.en_passant);
+        assert_eq!(0, position.halfmove_clock);
+        assert_eq!(1, position.fullmove_number);
+        assert_eq!(position::STARTING_FEN, position.to_fen());
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 12 40",
+        ] {
+            assert_eq!(fen, position::Position::from_fen(fen).unwrap().to_fen());
+        }
+
+        // The array board handed to the per-piece move functions carries them as has_moved
+        let position = position::Position::from_fen("r3k2r/8/8/8/8/8/4P3/R3K2R b Kq - 12 40").unwrap();
+        let board = position.board();
+        assert!(!board[7][7].has_moved);
+        assert!(board[7][0].has_moved);
+        assert!(!board[7][4].has_moved);
+        assert!(board[0][7].has_moved);
+        assert!(!board[6][4].has_moved);
+
+        // Missing clocks default to the start of the game
+        let position = position::Position::from_fen("8/8/8/8/8/8/8/K6k w - -").unwrap();
+        assert_eq!((0, 1), (position.halfmove_clock, position.fullmove_number));
+    }
+
+    #[test]
+    fn fen_errors() {
+        use position::{FenError, Position};
+
+        assert_eq!(Err(FenError::WrongFieldCount(3)), Position::from_fen("8/8/8/8/8/8/8/K6k w -"));
+        assert_eq!(Err(FenError::WrongRankCount(7)), Position::from_fen("8/8/8/8/8/8/K6k w - - 0 1"));
+        assert_eq!(Err(FenError::BadRankLength { rank: 5, squares: 9 }), Position::from_fen("8/8/8/8p/8/8/8/K6k w - - 0 1"));
+        assert_eq!(Err(FenError::BadRankLength { rank: 1, squares: 7 }), Position::from_fen("8/8/8/8/8/8/8/K5k w - - 0 1"));
+        assert_eq!(Err(FenError::UnknownPiece { rank: 8, symbol: 'x' }), Position::from_fen("x7/8/8/8/8/8/8/K6k w - - 0 1"));
+        assert_eq!(Err(FenError::WrongKingCount { color: Color::Black, count: 0 }), Position::from_fen("8/8/8/8/8/8/8/K7 w - - 0 1"));
+        assert_eq!(Err(FenError::BadSideToMove("x".to_string())), Position::from_fen("8/8/8/8/8/8/8/K6k x - - 0 1"));
+        assert_eq!(Err(FenError::BadCastling("KK".to_string())), Position::from_fen("k7/8/8/8/8/8/8/4K2R w KK - 0 1"));
+        assert_eq!(Err(FenError::CastlingWithoutPieces('Q')), Position::from_fen("8/8/8/8/8/8/8/4K2k w Q - 0 1"));
+        assert_eq!(Err(FenError::BadEnPassant("e4".to_string())), Position::from_fen("8/8/8/8/4P3/8/8/K6k b - e4 0 1"));
+        assert_eq!(Err(FenError::BadEnPassant("z9".to_string())), Position::from_fen("8/8/8/8/8/8/8/K6k w - z9 0 1"));
+        assert_eq!(Err(FenError::BadHalfmoveClock("-1".to_string())), Position::from_fen("8/8/8/8/8/8/8/K6k w - - -1 1"));
+        assert_eq!(Err(FenError::BadFullmoveNumber("0".to_string())), Position::from_fen("8/8/8/8/8/8/8/K6k w - - 0 0"));
+    }
+
+    #[test]
+    fn fen_round_trip_pawn_galore_double_step() {
+        // A Pawn Galore pawn double stepping off the back rank leaves its en passant square on the 2nd rank
+        let mut position = Position::from_fen("ppppkppp/pppppppp/8/8/P7/8/1PPPPPPP/PPPPKPPP w - - 0 1").unwrap();
+        position.make_move(&pieces_logic::Move { current_square: (7, 0), destination_square: (5, 0), castle: false, promotion: pieces_logic::Promotion::NoPromotion});
+
+        let fen = position.to_fen();
+        assert_eq!("ppppkppp/pppppppp/8/8/P7/P7/1PPPPPPP/1PPPKPPP b - a2 0 1", fen);
+        assert_eq!(Some((6, 0)), Position::from_fen(&fen).unwrap().en_passant);
+    }
+
+    #[test]
+    fn position_make_move() {
+        let mut position = Position::starting_position();
+        assert_eq!(20, position.legal_moves().len());
+
+        // 1. e4
+        position.make_move(&pieces_logic::Move { current_square: (6, 4), destination_square: (4, 4), castle: false, promotion: pieces_logic::Promotion::NoPromotion});
+        assert_eq!("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1", position.to_fen());
+
+        // 1... Nf6 2. Ke2
+        position.make_move(&pieces_logic::Move { current_square: (0, 6), destination_square: (2, 5), castle: false, promotion: pieces_logic::Promotion::NoPromotion});
+        position.make_move(&pieces_logic::Move { current_square: (7, 4), destination_square: (6, 4), castle: false, promotion: pieces_logic::Promotion::NoPromotion});
+        assert_eq!("rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPPKPPP/RNBQ1BNR b kq - 2 2", position.to_fen());
+        assert_eq!(Color::Black, position.side_to_move);
+
+        // Capturing a rook on its home square takes away that side's castling right
+        let mut position = Position::from_fen("r3k2r/8/8/8/8/8/6b1/R3K2R b KQkq - 0 1").unwrap();
+        position.make_move(&pieces_logic::Move { current_square: (6, 6), destination_square: (7, 7), castle: false, promotion: pieces_logic::Promotion::NoPromotion});
+        assert_eq!("r3k2r/8/8/8/8/8/8/R3K2b w Qkq - 0 2", position.to_fen());
+
+        let castles: Vec<pieces_logic::Move> = position.legal_moves().into_iter().filter(|m| m.castle).collect();
+        assert_eq!(castles, [pieces_logic::Move { current_square: (7, 4), destination_square: (7, 2), castle: true, promotion: pieces_logic::Promotion::NoPromotion}]);
+    }
+
+    #[test]
+    fn position_game_states() {
+        assert!(Position::from_fen("7k/8/8/8/8/8/5PPP/r5K1 w - - 0 1").unwrap().is_checkmate());
+        assert!(Position::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap().is_stalemate());
+        assert!(Position::from_fen("7k/8/8/8/8/8/8/6KN w - - 0 1").unwrap().is_insufficient_material());
+
+        let position = Position::from_fen("7k/8/8/8/8/8/8/r5K1 w - - 0 1").unwrap();
+        assert!(position.is_in_check());
+        assert!(!position.is_checkmate());
+        assert!(position.evaluate() < 0);
+    }
+
+    // Reference counts from https://www.chessprogramming.org/Perft_Results
+    #[test]
+    fn perft_reference_positions() {
+        let suite: [(&str, &[u64]); 8] = [
+            (position::STARTING_FEN, &[20, 400, 8_902, 197_281]),
+            // Kiwipete
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2_039, 97_862]),
+            // En passant pins along the rank
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2_812, 43_238]),
+            // Promotions and castling under attack, and its mirror
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9_467]),
+            ("r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1", &[6, 264, 9_467]),
+            ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1_486, 62_379]),
+            ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", &[46, 2_079, 89_890]),
+            // Castling rights only
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", &[26, 568, 13_744]),
+        ];
+
+        for (fen, counts) in suite {
+            let mut position = Position::from_fen(fen).unwrap();
+            for (depth, expected) in counts.iter().enumerate() {
+                assert_eq!(*expected, perft::perft(&mut position, depth as u8 + 1), "{} depth {}", fen, depth + 1);
+            }
+            assert_eq!(fen, position.to_fen());
+        }
+    }
+
+    #[test]
+    fn bitboard_slider_attacks() {
+        // Rook on d4 with blockers on d6 and f4.
+        let d4 = bitboard::square_index((4, 3));
+        let occupied = bitboard::bit(bitboard::square_index((2, 3))) | bitboard::bit(bitboard::square_index((4, 5)));
+        let attacks = bitboard::rook_attacks(d4, occupied);
+
+        assert_eq!(10, attacks.count_ones());
+        assert!(attacks & bitboard::bit(bitboard::square_index((2, 3))) != 0);
+        assert!(attacks & bitboard::bit(bitboard::square_index((1, 3))) == 0);
+        assert!(attacks & bitboard::bit(bitboard::square_index((4, 6))) == 0);
+
+        assert_eq!(13, bitboard::bishop_attacks(d4, 0).count_ones());
+        assert_eq!(27, bitboard::queen_attacks(d4, 0).count_ones());
+        assert_eq!(2, bitboard::between(d4, bitboard::square_index((7, 0))).count_ones());
+    }
+
+    #[test]
+    fn bitboard_moves_match_board_moves() {
+        let fens = [
+            position::STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            // Pawn Galore
+            "ppppkppp/pppppppp/8/8/8/8/PPPPPPPP/PPPPKPPP w - - 0 1",
+        ];
+
+        let z = pieces_logic::Zobrist::new();
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+
+        for fen in fens {
+            for _ in 0..4 {
+                let mut position = Position::from_fen(fen).unwrap();
+                let mut hash = position.hash(&z);
+                // Played along with the array make_move, so its has_moved flags come from real moves.
+                let mut board = position.board();
+
+                for _ in 0..80 {
+                    let mut moves = position.legal_moves();
+                    let mut expected = pieces_logic::get_all_legal_moves_for_this_turn(
+                        &board,
+                        position.side_to_move,
+                        position.en_passant,
+                    );
+                    expected.retain(|m| {
+                        !m.castle || position.castling.allows(position.side_to_move, m.destination_square.1 > m.current_square.1)
+                    });
+                    moves.sort();
+                    expected.sort();
+                    assert_eq!(expected, moves, "{}", position.to_fen());
+
+                    if moves.is_empty() {
+                        break;
+                    }
+
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    let mv = moves[(seed % moves.len() as u64) as usize];
+
+                    hash = position.hash_after_move(hash, &mv, &z);
+                    position.make_move(&mv);
+                    pieces_logic::make_move(&mut board, &mv);
+                    assert_eq!(position.hash(&z), hash, "{}", position.to_fen());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn moved_pawn_loses_double_step() {
+        let mut position = Position::from_board(chess_board::initialize_pawn_galore_board(), Color::White);
+        let mut board = chess_board::initialize_pawn_galore_board();
+
+        // 1. a4 h6 2. a5 h5 3. a2 h4: the a-pawn now on a2 came up from the back rank.
+        for (from, to) in [((6, 0), (4, 0)), ((1, 7), (2, 7)), ((4, 0), (3, 0)), ((2, 7), (3, 7)), ((7, 0), (6, 0)), ((3, 7), (4, 7))] {
+            let mv = pieces_logic::Move { current_square: from, destination_square: to, castle: false, promotion: pieces_logic::Promotion::NoPromotion};
+            position.make_move(&mv);
+            pieces_logic::make_move(&mut board, &mv);
+        }
+
+        let a2 = |moves: Vec<pieces_logic::Move>| {
+            let mut targets: Vec<(u8, u8)> = moves.iter().filter(|m| m.current_square == (6, 0)).map(|m| m.destination_square).collect();
+            targets.sort();
+            targets
+        };
+        assert_eq!(vec![(5, 0)], a2(position.legal_moves()));
+        assert_eq!(vec![(5, 0)], a2(pieces_logic::get_all_legal_moves_for_this_turn(&board, Color::White, position.en_passant)));
+        // The GUI board keeps the pawn's history too.
+        assert!(position.board()[6][0].has_moved);
+        assert!(!position.board()[6][1].has_moved);
+    }
+
+    #[test]
+    fn position_unmake_move() {
+        fn walk(position: &mut Position, depth: u8, z: &pieces_logic::Zobrist) {
+            if depth == 0 {
+                return;
+            }
+            let before = *position;
+            let hash = position.hash(z);
+
+            for mv in position.legal_moves() {
+                let undo = position.make_move(&mv);
+                walk(position, depth - 1, z);
+                position.unmake_move(&mv, undo);
+
+                assert_eq!(before, *position, "{:?}", mv);
+                assert_eq!(hash, position.hash(z));
+            }
+        }
+
+        let z = pieces_logic::Zobrist::new();
+        // Castling both ways, en passant, promotions with and without capture.
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 3 9",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+        ] {
+            let mut position = Position::from_fen(fen).unwrap();
+            walk(&mut position, 3, &z);
+        }
+    }
+
+    #[test]
+    fn perft_edge_cases() {
+        // From Martin Sedlak's list of generator edge cases
+        let suite = [
+            // Castling through and into check
+            ("5k2/8/8/8/8/8/8/4K2R w K - 0 1", 6, 661_072),
+            ("3k4/8/8/8/8/8/8/R3K3 w Q - 0 1", 6, 803_711),
+            // Promotions, including out of and into check
+            ("4k3/1P6/8/8/8/8/K7/8 w - - 0 1", 6, 217_342),
+            ("8/P1k5/K7/8/8/8/8/8 w - - 0 1", 6, 92_683),
+            ("K1k5/8/P7/8/8/8/8/8 w - - 0 1", 6, 2_217),
+            // Self stalemate and checkmate
+            ("8/8/2k5/5q2/5n2/8/5K2/8 b - - 0 1", 4, 23_527),
+        ];
+
+        for (fen, depth, expected) in suite {
+            let mut position = Position::from_fen(fen).unwrap();
+            assert_eq!(expected, perft::perft(&mut position, depth), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn perft_divide() {
+        let mut position = Position::starting_position();
+        let divide = perft::perft_divide(&mut position, 3);
+
+        assert_eq!(20, divide.len());
+        assert_eq!(8_902, divide.iter().map(|(_, n)| n).sum::<u64>());
+
+        let e2e4 = divide.iter().find(|(mv, _)| pieces_logic::move_to_universal_chess_interface(mv) == "e2e4").unwrap();
+        assert_eq!(600, e2e4.1);
+    }
+
+    #[test]
+    fn uci_position_command() {
+        let position = uci::parse_position(&["startpos", "moves", "e2e4", "d7d5", "e4e5", "f7f5", "e5f6"]).unwrap();
+        assert_eq!("rnbqkbnr/ppp1p1pp/5P2/3p4/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3", position.to_fen());
+
+        let fen = "r3k2r/1P6/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let args: Vec<&str> = ["fen"].into_iter().chain(fen.split(' ')).chain(["moves", "e1c1", "e8g8", "b7b8q"]).collect();
+        let position = uci::parse_position(&args).unwrap();
+        assert_eq!("rQ3rk1/8/8/8/8/8/8/2KR3R b - - 0 2", position.to_fen());
+
+        // The b7 pawn covers c8, so black can't castle long
+        let args: Vec<&str> = ["fen"].into_iter().chain(fen.split(' ')).chain(["moves", "e1g1", "e8c8"]).collect();
+        assert!(uci::parse_position(&args).is_err());
+
+        assert!(uci::parse_position(&["startpos", "moves", "e2e5"]).is_err());
+        assert!(uci::parse_position(&["fen", "8/8/8/8/8/8/8/8", "w", "-", "-"]).is_err());
+        assert!(uci::parse_position(&["somewhere"]).is_err());
+    }
+
+    #[test]
+    fn uci_go_command() {
+        let params = uci::GoParams::parse(&["wtime", "60000", "btime", "30000", "winc", "1000", "binc", "0"]);
+        assert_eq!(Some(60_000), params.wtime);
+        assert_eq!(None, params.depth);
+
+        let limits = params.limits(Color::White);
+        assert_eq!(Some(Duration::from_secs(60)), limits.time);
+        assert_eq!(Duration::from_secs(1), limits.increment);
+        assert_eq!(time_manager::MAX_DEPTH, limits.depth);
+
+        let time = time_manager::TimeManager::new(&limits);
+        assert_eq!(Some(Duration::from_millis(2_750)), time.optimum());
+        assert_eq!(Some(Duration::from_millis(11_000)), time.maximum());
+
+        let time = time_manager::TimeManager::new(&params.limits(Color::Black));
+        assert_eq!(Some(Duration::from_millis(1_000)), time.optimum());
+        assert_eq!(Some(Duration::from_millis(4_000)), time.maximum());
+
+        // The last move before the time control may use all of it, minus the overhead.
+        let params = uci::GoParams::parse(&["btime", "100", "movestogo", "1"]);
+        let time = time_manager::TimeManager::new(&params.limits(Color::Black));
+        assert_eq!(Some(Duration::from_millis(50)), time.maximum());
+        assert_eq!(None, time_manager::TimeManager::new(&params.limits(Color::White)).maximum());
+
+        let limits = uci::GoParams::parse(&["movetime", "500", "nodes", "20000"]).limits(Color::White);
+        assert_eq!(Some(20_000), limits.nodes);
+        assert_eq!(Some(Duration::from_millis(450)), time_manager::TimeManager::new(&limits).maximum());
+
+        assert_eq!(7, uci::GoParams::parse(&["depth", "7"]).limits(Color::White).depth);
+        let limits = uci::GoParams::parse(&["infinite", "wtime", "1000"]).limits(Color::White);
+        assert_eq!(time_manager::SearchLimits::default(), limits);
+    }
+
+    #[test]
+    fn uci_go_negative_clock() {
+        let params = uci::GoParams::parse(&["wtime", "-50", "btime", "1000", "winc", "-10"]);
+        let limits = params.limits(Color::White);
+        assert_eq!(Some(Duration::ZERO), limits.time);
+        assert_eq!(Duration::ZERO, limits.increment);
+
+        // Out of time still means the smallest budget, never an unlimited search.
+        let time = time_manager::TimeManager::new(&limits);
+        assert!(time.maximum().is_some_and(|maximum| maximum <= Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn uci_info_line() {
+        let position = Position::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 2 3").unwrap();
+        let mut lines = vec![];
+        let stop = AtomicBool::new(false);
+        let limits = SearchLimits::depth(3);
+        let best = pieces_logic::search_iterative(&position, &limits, &SearchOptions::default(), &stop, |info| lines.push(uci::format_info(info)));
+
+        assert_eq!("f3f7", pieces_logic::move_to_universal_chess_interface(&best));
+        assert_eq!(3, lines.len());
+        // Quiescence already sees the mate after Qxf7 at depth 1.
+        assert!(lines[0].starts_with("info depth 1 score mate 1 nodes "));
+        assert!(lines[2].starts_with("info depth 3 score mate 1 nodes "));
+        assert!(lines[2].ends_with(" pv f3f7"));
+    }
+
+    #[test]
+    fn uci_setoption_command() {
+        assert_eq!(Some(("Null Move".to_string(), Some("false".to_string()))), uci::parse_setoption(&["name", "Null", "Move", "value", "false"]));
+        assert_eq!(Some(("Clear Hash".to_string(), None)), uci::parse_setoption(&["name", "Clear", "Hash"]));
+        assert_eq!(None, uci::parse_setoption(&["value", "1"]));
+
+        let mut engine = uci::Uci::new();
+        engine.handle_command("setoption name NullMove value false");
+        engine.handle_command("setoption name Futility value false");
+        engine.handle_command("setoption name LateMoveReductions value maybe");
+        assert_eq!(
+            SearchOptions { null_move: false, futility: false, ..SearchOptions::default() },
+            *engine.options()
+        );
+    }
+
+    #[test]
+    fn uci_commands_during_infinite_search() {
+        // Anything but `stop` sent during `go infinite` ends the search instead of waiting on it.
+        let mut engine = uci::Uci::new();
+        engine.handle_command("go infinite");
+        engine.handle_command("setoption name MultiPV value 2");
+        engine.handle_command("go infinite");
+        engine.handle_command("position startpos moves e2e4");
+        engine.handle_command("go infinite");
+        engine.handle_command("ucinewgame");
+        engine.handle_command("go depth 1");
+        assert_eq!(2, engine.options().multi_pv);
+        drop(engine.engine());
+    }
+
+    #[test]
+    fn xboard_level_command() {
+        let tc = xboard::TimeControl::parse(&["40", "5", "0"]).unwrap();
+        assert_eq!(40, tc.moves);
+        assert_eq!(Duration::from_secs(300), tc.base);
+        assert_eq!(Duration::ZERO, tc.increment);
+
+        let tc = xboard::TimeControl::parse(&["0", "2:30", "1.5"]).unwrap();
+        assert_eq!(0, tc.moves);
+        assert_eq!(Duration::from_secs(150), tc.base);
+        assert_eq!(Duration::from_millis(1_500), tc.increment);
+
+        assert_eq!(None, xboard::TimeControl::parse(&["40", "5"]));
+        assert_eq!(None, xboard::TimeControl::parse(&["40", "five", "0"]));
+        assert_eq!(None, xboard::TimeControl::parse(&["40", "5", "-1"]));
+        assert_eq!(None, xboard::TimeControl::parse(&["0", "5", "inf"]));
+    }
+
+    #[test]
+    fn xboard_game_flow() {
+        let (tx, rx) = mpsc::channel();
+        let mut xb = xboard::XBoard::new(tx);
+
+        for command in ["new", "force", "usermove e2e4", "usermove e7e5", "usermove e2e5", "usermove g1f3"] {
+            assert!(xb.handle_command(command));
+        }
+        assert_eq!("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2", xb.position().to_fen());
+
+        xb.handle_command("undo");
+        assert_eq!("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2", xb.position().to_fen());
+        xb.handle_command("remove");
+        assert_eq!(position::STARTING_FEN, xb.position().to_fen());
+
+        // Engine to move in a mate in one
+        xb.handle_command("setboard r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 2 3");
+        xb.handle_command("sd 2");
+        xb.handle_command("go");
+        let xboard::Event::BestMove { search_id, best } = rx.recv().unwrap() else {
+            panic!("expected the engine's move");
+        };
+        assert_eq!("f3f7", pieces_logic::move_to_universal_chess_interface(&best));
+
+        xb.handle_best_move(search_id, best);
+        assert!(xb.position().is_checkmate());
+        assert_eq!(Some("1-0 {White mates}"), xboard::game_result(xb.position()));
+
+        assert!(!xb.handle_command("quit"));
+    }
+
+    #[test]
+    fn search_stop_flag() {
+        let position = Position::starting_position();
+
+        // Already stopped: no iteration completes, but the answer is still a legal move.
+        let stop = AtomicBool::new(true);
+        let mut iterations = 0;
+        let best = pieces_logic::search_iterative(&position, &SearchLimits::depth(5), &SearchOptions::default(), &stop, |_| iterations += 1);
+        assert_eq!(0, iterations);
+        assert!(position.legal_moves().contains(&best));
+
+        // Stopped in the middle of a search that would otherwise run for ages.
+        let stop = Arc::new(AtomicBool::new(false));
+        let search_stop = stop.clone();
+        let search = thread::spawn(move || {
+            let mut completed = vec![];
+            let best = pieces_logic::search_iterative(&position, &SearchLimits::default(), &SearchOptions::default(), &search_stop, |info| {
+                completed.push(info.best_move)
+            });
+            (best, completed)
+        });
+
+        thread::sleep(Duration::from_millis(300));
+        let stopped_at = Instant::now();
+        stop.store(true, Ordering::Relaxed);
+        let (best, completed) = search.join().unwrap();
+
+        assert!(stopped_at.elapsed() < Duration::from_millis(500));
+        assert!(!completed.is_empty() && completed.len() < 64);
+        assert_eq!(*completed.last().unwrap(), best);
+    }
+
+    #[test]
+    fn search_limits() {
+        let position = Position::starting_position();
+        let stop = AtomicBool::new(false);
+
+        // A fixed move time is kept even when the depth would take far longer.
+        let limits = SearchLimits { movetime: Some(Duration::from_millis(300)), ..SearchLimits::default() };
+        let start = Instant::now();
+        let mut completed = vec![];
+        let best = pieces_logic::search_iterative(&position, &limits, &SearchOptions::default(), &stop, |info| completed.push(info.depth));
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(!completed.is_empty());
+        assert!(position.legal_moves().contains(&best));
+
+        // Node limits are exact: no iteration reports more than it allows.
+        let limits = SearchLimits { nodes: Some(5_000), ..SearchLimits::default() };
+        let mut nodes = vec![];
+        pieces_logic::search_iterative(&position, &limits, &SearchOptions::default(), &stop, |info| nodes.push(info.nodes));
+        assert!(!nodes.is_empty() && nodes.iter().all(|n| *n <= 5_000));
+
+        // Plenty of time left on the clock: the depth limit ends the search.
+        let limits = SearchLimits { depth: 3, time: Some(Duration::from_secs(60)), ..SearchLimits::default() };
+        let mut last_depth = 0;
+        pieces_logic::search_iterative(&position, &limits, &SearchOptions::default(), &stop, |info| last_depth = info.depth);
+        assert_eq!(3, last_depth);
+
+        // Once the optimum is spent no new iteration starts.
+        let time = time_manager::TimeManager::new(&SearchLimits { movetime: Some(Duration::ZERO), ..SearchLimits::default() });
+        assert!(!time.start_iteration(Duration::ZERO));
+        assert!(time.out_of_time());
+        let time = time_manager::TimeManager::new(&SearchLimits { movetime: Some(Duration::from_secs(10)), ..SearchLimits::default() });
+        assert!(time.start_iteration(Duration::from_secs(1)));
+        assert!(!time.start_iteration(Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn quiescence_search() {
+        let stop = AtomicBool::new(false);
+
+        // Nothing to capture: the static evaluation stands.
+        let mut position = Position::starting_position();
+        let engine = pieces_logic::Engine::default();
+        let mut ctx = pieces_logic::SearchContext::new(&SearchLimits::default(), &SearchOptions::default(), &engine, &stop);
+        let score = pieces_logic::quiescence(&mut position, 0, -pieces_logic::INFINITY, pieces_logic::INFINITY, &mut ctx);
+        assert_eq!(position.evaluate(), score);
+        assert_eq!(1, ctx.nodes);
+
+        // d5 is defended by the c6 pawn; a one ply search must still see the recapture.
+        let position = Position::from_fen("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+        let best = pieces_logic::search_iterative(&position, &SearchLimits::depth(1), &SearchOptions::default(), &stop, |_| {});
+        assert_ne!("d1d5", pieces_logic::move_to_universal_chess_interface(&best));
+
+        // Hanging queen on d5 gets taken even at depth 1.
+        let position = Position::from_fen("4k3/8/8/3q4/8/8/8/3QK3 w - - 0 1").unwrap();
+        let best = pieces_logic::search_iterative(&position, &SearchLimits::depth(1), &SearchOptions::default(), &stop, |_| {});
+        assert_eq!("d1d5", pieces_logic::move_to_universal_chess_interface(&best));
+    }
+
+    #[test]
+    fn tapered_evaluation() {
+        let eval = |fen: &str| {
+            let position = Position::from_fen(fen).unwrap();
+            assert_eq!(pieces_logic::evaluate(&position.board()), position.evaluate());
+            position.evaluate()
+        };
+
+        assert_eq!(0, eval("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"));
+
+        // With everything on the board the castled king is safer than one out in the open...
+        let castled = eval("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQ1RK1 w - - 0 1");
+        let wandering = eval("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N1KN2/PPPP1PPP/R1BQ1R2 w - - 0 1");
+        assert!(castled >= wandering + 50, "{} vs {}", castled, wandering);
+
+        // ...but in a pawn ending it belongs in the centre.
+        let central = eval("8/5pk1/8/8/3K4/8/5P2/8 w - - 0 1");
+        let cornered = eval("8/5pk1/8/8/8/8/5P2/K7 w - - 0 1");
+        assert!(central > cornered + 50, "{} vs {}", central, cornered);
+
+        let score = pieces_logic::Score::new(100, 20);
+        assert_eq!(100, score.taper(24));
+        assert_eq!(60, score.taper(12));
+        assert_eq!(20, score.taper(0));
+        assert_eq!(100, score.taper(30));
+    }
+
+    #[test]
+    fn pawn_structure() {
+        let pawns = |fen: &str| pieces_logic::pawn_structure(&Position::from_fen(fen).unwrap());
+        let better = |a: &str, b: &str| {
+            let (a, b) = (pawns(a), pawns(b));
+            assert!(a.mg > b.mg && a.eg > b.eg, "{:?} vs {:?}", a, b);
+        };
+
+        // Passed pawns are worth more the further they got, and less with a piece in the way.
+        better("4k3/8/3P4/8/8/8/8/4K3 w - - 0 1", "4k3/8/8/8/3P4/8/8/4K3 w - - 0 1");
+        better("4k3/8/8/3P4/8/8/8/4K3 w - - 0 1", "4k3/8/3n4/3P4/8/8/8/4K3 w - - 0 1");
+        // Doubled and isolated pawns are weaknesses, connected ones a strength.
+        better("4k3/8/8/8/8/8/2PP4/4K3 w - - 0 1", "4k3/8/8/8/8/2P5/2P5/4K3 w - - 0 1");
+        better("4k3/8/8/8/8/8/PP6/4K3 w - - 0 1", "4k3/8/8/8/8/8/P1P5/4K3 w - - 0 1");
+        // d3 can't be supported by e4 and c5 guards d4: backward. With the pawn on c6 it isn't.
+        better("4k3/8/2p5/8/4P3/3P4/8/4K3 w - - 0 1", "4k3/8/8/2p5/4P3/3P4/8/4K3 w - - 0 1");
+
+        // Mirrored structures score the same for the other side.
+        let white = pawns("4k3/pp6/8/8/3P4/8/5PP1/4K3 w - - 0 1");
+        let black = pawns("4k3/5pp1/8/3p4/8/8/PP6/4K3 w - - 0 1");
+        assert_eq!(white.mg, -black.mg);
+        assert_eq!(white.eg, -black.eg);
+
+        // The pawn key only sees the pawns.
+        let z = pieces_logic::Zobrist::new();
+        let a = Position::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        let b = Position::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2").unwrap();
+        let c = Position::from_fen("rnbqkbnr/pppp1ppp/8/4p3/3PP3/8/PPP2PPP/RNBQKBNR b KQkq - 0 2").unwrap();
+        assert_eq!(a.pawn_hash(&z), b.pawn_hash(&z));
+        assert_ne!(a.pawn_hash(&z), c.pawn_hash(&z));
+        assert_ne!(a.hash(&z), b.hash(&z));
+    }
+
+    #[test]
+    fn doubled_passed_pawn() {
+        let pawns = |fen: &str| pieces_logic::pawn_structure(&Position::from_fen(fen).unwrap());
+        let p = eval_params::params();
+
+        // The pawn behind adds its weaknesses, not a second passer.
+        let single = pawns("4k3/8/8/3P4/8/8/8/4K3 w - - 0 1");
+        let doubled = pawns("4k3/8/8/3P4/3P4/8/8/4K3 w - - 0 1");
+        assert_eq!(single + p.doubled_pawn + p.isolated_pawn, doubled);
+    }
+
+    #[test]
+    fn king_safety_and_mobility() {
+        let activity = |fen: &str| pieces_logic::piece_activity(&Position::from_fen(fen).unwrap());
+
+        assert_eq!(pieces_logic::Activity::default(), activity("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"));
+
+        // Pushing the pawns in front of the castled king weakens it, opening a file even more so.
+        let intact = activity("r4rk1/ppp2ppp/8/8/8/8/PPP2PPP/R4RK1 w - - 0 1").king_safety;
+        let pushed = activity("r4rk1/ppp2ppp/8/8/8/6P1/PPP2P1P/R4RK1 w - - 0 1").king_safety;
+        let opened = activity("r4rk1/ppp2ppp/8/8/8/8/PPP2P1P/R4RK1 w - - 0 1").king_safety;
+        assert!(intact.mg > pushed.mg && pushed.mg > opened.mg, "{:?} {:?} {:?}", intact, pushed, opened);
+
+        // Queen and rook bearing down on the white king together are worth more than either alone.
+        let both = activity("6k1/5ppp/8/8/8/5q2/5PPP/4r1K1 w - - 0 1").king_safety;
+        let queen = activity("6k1/5ppp/8/8/8/5q2/5PPP/6K1 w - - 0 1").king_safety;
+        let rook = activity("6k1/5ppp/8/8/8/8/5PPP/4r1K1 w - - 0 1").king_safety;
+        assert!(both.mg < queen.mg && both.mg < rook.mg, "{:?} {:?} {:?}", both, queen, rook);
+
+        // A bishop on the open long diagonal beats one hemmed in by its own pawns.
+        let open = activity("4k3/8/8/8/8/8/8/B3K3 w - - 0 1").mobility;
+        let hemmed = activity("4k3/8/8/8/8/8/1P6/B3K3 w - - 0 1").mobility;
+        assert!(open.mg > hemmed.mg && open.eg > hemmed.eg);
+        // Squares guarded by enemy pawns don't count.
+        let guarded = activity("4k3/8/8/2p1p3/8/8/8/3NK3 w - - 0 1").mobility;
+        let free = activity("4k3/8/8/8/2p1p3/8/8/3NK3 w - - 0 1").mobility;
+        assert!(free.mg >= guarded.mg);
+    }
+
+    #[test]
+    fn pluggable_evaluator() {
+        use pieces_logic::{Engine, Evaluator, HandcraftedEvaluator};
+        use std::sync::atomic::AtomicU64;
+
+        // Loves a knight on a3, and counts how often it was asked.
+        #[derive(Default)]
+        struct KnightOnTheRim(AtomicU64);
+
+        impl Evaluator for KnightOnTheRim {
+            fn evaluate(&self, position: &Position) -> i64 {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                let rim = position.pieces(Color::White, Symbol::Knight) & bitboard::bit(40) != 0;
+                HandcraftedEvaluator.evaluate(position) + if rim { 1000 } else { 0 }
+            }
+        }
+
+        let position = Position::starting_position();
+        assert_eq!(position.evaluate(), HandcraftedEvaluator.evaluate(&position));
+
+        let stop = AtomicBool::new(false);
+        let mut engine = Engine::with_evaluator(1, KnightOnTheRim::default());
+        let best = engine.search(&position, &SearchLimits::depth(3), &SearchOptions::default(), &stop, |_| {});
+        assert_eq!("b1a3", pieces_logic::move_to_universal_chess_interface(&best));
+        assert!(engine.evaluator.0.load(Ordering::Relaxed) > 0);
+
+        let best = pieces_logic::search_iterative(&position, &SearchLimits::depth(3), &SearchOptions::default(), &stop, |_| {});
+        assert_ne!("b1a3", pieces_logic::move_to_universal_chess_interface(&best));
+
+        // The UCI front end plays with it just the same.
+        let mut uci = uci::Uci::with_engine(Engine::with_evaluator(1, KnightOnTheRim::default()));
+        uci.handle_command("go depth 2");
+        assert!(uci.engine().evaluator.0.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn eval_params_round_trip() {
+        use eval_params::EvalParams;
+        use pieces_logic::Score;
+
+        let defaults = EvalParams::default();
+        assert_eq!(900, pieces_logic::material_value(Symbol::Queen));
+
+        assert_eq!(defaults, EvalParams::from_json(&defaults.to_json()).unwrap());
+        assert_eq!(defaults, EvalParams::from_toml(&defaults.to_toml().unwrap()).unwrap());
+
+        // Whatever a file leaves out keeps its default.
+        let partial = EvalParams::from_json(r#"{"pawn_value": [90, 110]}"#).unwrap();
+        assert_eq!(Score::new(90, 110), partial.pawn_value);
+        assert_eq!(defaults.queen_value, partial.queen_value);
+        assert!(EvalParams::from_toml("pawn_value = \"lots\"").is_err());
+        let error: Box<dyn std::error::Error> = EvalParams::from_json("{").unwrap_err().into();
+        assert!(error.to_string().starts_with("invalid JSON"));
+
+        let path = std::env::temp_dir().join(format!("rockfish-eval-{}.toml", std::process::id()));
+        partial.save(&path).unwrap();
+        assert_eq!(partial, EvalParams::load(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(defaults.save(&std::env::temp_dir().join("rockfish-eval.yaml")).is_err());
+    }
+
+    #[test]
+    fn static_exchange_evaluation() {
+        let see = |fen: &str, uci: &str| {
+            let position = Position::from_fen(fen).unwrap();
+            let mv = position
+                .legal_moves()
+                .into_iter()
+                .find(|m| pieces_logic::move_to_universal_chess_interface(m) == uci)
+                .unwrap();
+            pieces_logic::see(&position, &mv)
+        };
+
+        // Undefended pawn.
+        assert_eq!(100, see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"));
+        // Knight for pawn once the queen behind the f6 bishop joins in, the rook and queen on
+        // the e-file can't save it.
+        assert_eq!(-220, see("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5"));
+        // Queen for pawn.
+        assert_eq!(-800, see("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", "d1d5"));
+        assert_eq!(100, see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"));
+        // The new queen is lost right away, so promoting only costs the pawn...
+        assert_eq!(-100, see("3rk3/2P5/8/8/8/8/8/4K3 w - - 0 1", "c7c8q"));
+        // ...unless it took a rook on the way.
+        assert_eq!(400, see("3rk3/2P5/8/8/8/8/8/4K3 w - - 0 1", "c7d8q"));
+
+        // Losing captures go behind the quiet moves.
+        let position = Position::from_fen("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+        let mut moves = position.legal_moves();
+        pieces_logic::order_moves_soft_in_negamax(&position, &mut moves, |_| 0);
+        assert_eq!("d1d5", pieces_logic::move_to_universal_chess_interface(moves.last().unwrap()));
+    }
+
+    #[test]
+    fn quiet_move_history() {
+        let position = Position::starting_position();
+        let find = |uci: &str| {
+            *position.legal_moves().iter().find(|m| pieces_logic::move_to_universal_chess_interface(m) == uci).unwrap()
+        };
+        let (e4, d4, nf3, c4) = (find("e2e4"), find("d2d4"), find("g1f3"), find("c2c4"));
+        let reply = find("b1c3");
+
+        let mut history = pieces_logic::History::new();
+        history.on_cutoff(Color::White, &e4, 4, 2, Some(reply), &[d4]);
+        history.on_cutoff(Color::White, &nf3, 3, 2, None, &[]);
+
+        // Newest killer first, then the older one, then the counter move, then plain history.
+        let score = |mv: &Move, ply, previous| history.quiet_score(Color::White, mv, ply, previous);
+        assert!(score(&nf3, 2, None) > score(&e4, 2, None));
+        assert!(score(&e4, 2, None) > score(&e4, 5, Some(reply)));
+        assert!(score(&e4, 5, Some(reply)) > score(&e4, 5, None));
+        assert!(score(&e4, 5, None) > score(&c4, 5, None));
+        assert!(score(&d4, 5, None) < 0);
+        assert_eq!(0, history.quiet_score(Color::Black, &e4, 5, None));
+
+        let mut moves = position.legal_moves();
+        pieces_logic::order_moves_soft_in_negamax(&position, &mut moves, |m| score(m, 2, None));
+        assert_eq!([nf3, e4], moves[..2]);
+        assert_eq!(d4, *moves.last().unwrap());
+    }
+
+    #[test]
+    fn null_move() {
+        let z = pieces_logic::Zobrist::new();
+        let mut position = Position::from_fen("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPP2PPP/RNBQKBNR b KQkq e3 0 3").unwrap();
+        let before = position;
+        let hash = position.hash(&z);
+
+        let undo = position.make_null_move();
+        assert_eq!(Color::White, position.side_to_move);
+        assert_eq!(None, position.en_passant);
+        assert_eq!(position.hash(&z), before.hash_after_null_move(hash, &z));
+
+        position.unmake_null_move(undo);
+        assert_eq!(before.to_fen(), position.to_fen());
+
+        assert!(position.has_non_pawn_material(Color::White));
+        assert!(!Position::from_fen("4k3/pp6/8/8/8/8/6PP/4K2N w - - 0 1").unwrap().has_non_pawn_material(Color::Black));
+
+        // Pruning changes how much gets searched, not what a short forced line is worth.
+        let position = Position::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 2 3").unwrap();
+        let stop = AtomicBool::new(false);
+        let off = SearchOptions { null_move: false, late_move_reductions: false, reverse_futility: false, futility: false, ..SearchOptions::default() };
+        for options in [SearchOptions::default(), off] {
+            let best = pieces_logic::search_iterative(&position, &SearchLimits::depth(4), &options, &stop, |_| {});
+            assert_eq!("f3f7", pieces_logic::move_to_universal_chess_interface(&best));
+        }
+    }
+
+    #[test]
+    fn mate_scores() {
+        let stop = AtomicBool::new(false);
+        let search = |fen: &str, depth: u8| {
+            let position = Position::from_fen(fen).unwrap();
+            let mut mates = vec![];
+            let best = pieces_logic::search_iterative(&position, &SearchLimits::depth(depth), &SearchOptions::default(), &stop, |info| {
+                mates.push(info.mate_in())
+            });
+            (pieces_logic::move_to_universal_chess_interface(&best), mates)
+        };
+
+        // The distance must not drift once the mate is found and comes back out of the TT at other plies.
+        let (best, mates) = search("r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1", 6);
+        assert_eq!("d5f6", best);
+        assert_eq!(Some(2), mates[5]);
+        assert!(mates.iter().flatten().all(|m| *m == 2));
+
+        // With a back rank mate on the board, longer mates don't get in the way.
+        let (best, mates) = search("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", 5);
+        assert_eq!("a1a8", best);
+        assert!(mates.iter().all(|m| *m == Some(1)));
+
+        // The side getting mated sees it coming.
+        let (_, mates) = search("k7/8/1K6/8/8/8/8/7R b - - 0 1", 4);
+        assert_eq!(Some(-1), *mates.last().unwrap());
+    }
+
+    #[test]
+    fn principal_variation() {
+        let stop = AtomicBool::new(false);
+        let position = Position::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+        let mut infos = vec![];
+        let best = pieces_logic::search_iterative(&position, &SearchLimits::depth(5), &SearchOptions::default(), &stop, |info| {
+            infos.push(info.clone())
+        });
+
+        assert_eq!(5, infos.len());
+        assert_eq!(best, infos[4].pv[0]);
+        for info in &infos {
+            assert_eq!(info.best_move, info.pv[0]);
+            assert!(info.pv.len() >= info.depth as usize, "{:?}", info.pv);
+
+            // Every move has to be legal where it's played.
+            let mut line = position;
+            for mv in &info.pv {
+                assert!(line.legal_moves().contains(mv));
+                line.make_move(mv);
+            }
+        }
+
+        // A mate's PV ends with the mate.
+        let position = Position::from_fen("r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1").unwrap();
+        let mut pv = vec![];
+        pieces_logic::search_iterative(&position, &SearchLimits::depth(4), &SearchOptions::default(), &stop, |info| pv = info.pv.clone());
+        assert_eq!(3, pv.len());
+        assert_eq!("d5f6", pieces_logic::move_to_universal_chess_interface(&pv[0]));
+        let mut line = position;
+        for mv in &pv {
+            line.make_move(mv);
+        }
+        assert!(line.is_checkmate());
+
+        let info = SearchInfo { depth: 4, score: -40, nodes: 0, elapsed: Duration::ZERO, hashfull: 0, best_move: infos[0].best_move, pv: vec![], lines: vec![] };
+        assert_eq!("+0.40", format_score(&info, Color::Black));
+        assert_eq!("-0.40", format_score(&info, Color::White));
+        let info = SearchInfo { score: pieces_logic::MATE_SCORE - 3, ..info };
+        assert_eq!("#-2", format_score(&info, Color::Black));
+    }
+
+    #[test]
+    fn multi_pv() {
+        let stop = AtomicBool::new(false);
+        let options = SearchOptions { multi_pv: 3, ..SearchOptions::default() };
+        let position = Position::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 2 3").unwrap();
+
+        let mut infos = vec![];
+        let best = pieces_logic::search_iterative(&position, &SearchLimits::depth(4), &options, &stop, |info| infos.push(info.clone()));
+
+        for info in &infos {
+            assert_eq!(3, info.lines.len());
+            assert_eq!(info.best_move, info.lines[0].best_move);
+            assert_eq!(info.pv, info.lines[0].pv);
+            for (i, line) in info.lines.iter().enumerate() {
+                assert_eq!(line.best_move, line.pv[0]);
+                assert!(info.lines[..i].iter().all(|other| other.best_move != line.best_move));
+                if i > 0 {
+                    assert!(line.score <= info.lines[i - 1].score);
+                }
+            }
+        }
+
+        // The mate comes first, the lines after it are nowhere near as good.
+        let last = infos.last().unwrap();
+        assert_eq!("f3f7", pieces_logic::move_to_universal_chess_interface(&best));
+        assert_eq!(Some(1), last.lines[0].mate_in());
+        assert_eq!(None, last.lines[1].mate_in());
+
+        let output = uci::format_info(last);
+        let output: Vec<&str> = output.lines().collect();
+        assert_eq!(3, output.len());
+        assert!(output[0].starts_with("info depth 4 multipv 1 score mate 1 nodes "));
+        assert!(output[2].starts_with("info depth 4 multipv 3 score cp "));
+
+        // More lines than moves: every move gets one.
+        let position = Position::from_fen("7k/8/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let options = SearchOptions { multi_pv: 10, ..SearchOptions::default() };
+        let mut lines = 0;
+        pieces_logic::search_iterative(&position, &SearchLimits::depth(2), &options, &stop, |info| lines = info.lines.len());
+        assert_eq!(3, lines);
+
+        let mut engine = uci::Uci::new();
+        engine.handle_command("setoption name MultiPV value 4");
+        engine.handle_command("setoption name MultiPV value 0");
+        assert_eq!(4, engine.options().multi_pv);
+    }
+
+    #[test]
+    fn lazy_smp() {
+        use pieces_logic::{TranspositionTable, TtEntry, TtFlag};
+
+        // Entries survive being packed into the lockless slots, mate scores and promotions included.
+        let tt = TranspositionTable::new(1);
+        let best = Move { current_square: (1, 4), destination_square: (0, 4), castle: false, promotion: Promotion::Knight };
+        let value = -(pieces_logic::MATE_SCORE - 3);
+        tt.store(TtEntry { key: 0x1234_5678_9ABC_DEF0, depth: 7, value, flag: TtFlag::LowerBound, best, best_valid: true });
+        let entry = tt.probe(0x1234_5678_9ABC_DEF0).unwrap();
+        assert_eq!((7, value, TtFlag::LowerBound, best, true), (entry.depth, entry.value, entry.flag, entry.best, entry.best_valid));
+        assert!(tt.probe(0x1234_5678_9ABC_DEF0 ^ 1).is_none());
+
+        let stop = AtomicBool::new(false);
+        let options = SearchOptions { threads: 4, ..SearchOptions::default() };
+        let position = Position::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 2 3").unwrap();
+        let mut infos = vec![];
+        let best = pieces_logic::search_iterative(&position, &SearchLimits::depth(5), &options, &stop, |info| infos.push(info.clone()));
+        assert_eq!("f3f7", pieces_logic::move_to_universal_chess_interface(&best));
+        assert_eq!(5, infos.len());
+        assert_eq!(Some(1), infos[4].mate_in());
+        assert!(infos.windows(2).all(|w| w[0].nodes <= w[1].nodes));
+
+        // The node limit counts the helpers' nodes too.
+        let limits = SearchLimits { nodes: Some(20_000), ..SearchLimits::default() };
+        let mut nodes = 0;
+        pieces_logic::search_iterative(&Position::starting_position(), &limits, &options, &stop, |info| nodes = info.nodes);
+        assert!(nodes > 0 && nodes <= 20_000);
+
+        let mut engine = uci::Uci::new();
+        engine.handle_command("setoption name Threads value 8");
+        engine.handle_command("setoption name Threads value 0");
+        assert_eq!(8, engine.options().threads);
+    }
+
+    #[test]
+    fn persistent_transposition_table() {
+        use pieces_logic::{TranspositionTable, TtEntry, TtFlag};
+
+        let entry = |key: u64, depth: u8| TtEntry {
+            key,
+            depth,
+            value: depth as i64,
+            flag: TtFlag::Exact,
+            best: Move { current_square: (6, 4), destination_square: (4, 4), castle: false, promotion: Promotion::NoPromotion },
+            best_valid: true,
+        };
+        // Keys from the same bucket: a one megabyte table has 16384 of them, picked by the top bits.
+        let key = |i: u64| (1 << 63) | i;
+
+        let mut tt = TranspositionTable::new(1);
+        assert_eq!(0, tt.hashfull());
+        for i in 1..=4 {
+            tt.store(entry(key(i), 10 + i as u8));
+        }
+        // The bucket is full: a shallower entry of the same search takes the shallowest slot.
+        tt.store(entry(key(5), 1));
+        assert!(tt.probe(key(1)).is_none());
+        assert!((2..=5).all(|i| tt.probe(key(i)).is_some()));
+        // A shallower result for a stored position doesn't replace a deeper one.
+        tt.store(entry(key(4), 2));
+        assert_eq!(14, tt.probe(key(4)).unwrap().depth);
+
+        // Next search: stale entries go first, even deep ones, and deeper results of theirs get in.
+        tt.new_search();
+        tt.store(entry(key(6), 0));
+        assert_eq!(3, (2..=5).filter(|&i| tt.probe(key(i)).is_some()).count());
+        tt.store(entry(key(4), 2));
+        assert_eq!(2, tt.probe(key(4)).unwrap().depth);
+
+        tt.clear();
+        assert!((1..=6).all(|i| tt.probe(key(i)).is_none()));
+
+        // A second search of the same position starts from what the first one left behind.
+        let stop = AtomicBool::new(false);
+        let position = Position::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+        let mut engine = pieces_logic::Engine::new(4);
+        let mut first = 0;
+        let mut hashfull = 0;
+        engine.search(&position, &SearchLimits::depth(5), &SearchOptions::default(), &stop, |info| (first, hashfull) = (info.nodes, info.hashfull));
+        let mut second = 0;
+        engine.search(&position, &SearchLimits::depth(5), &SearchOptions::default(), &stop, |info| second = info.nodes);
+        assert!(hashfull > 0);
+        assert!(second < first / 2, "{} then {}", first, second);
+
+        let mut engine = uci::Uci::new();
+        engine.handle_command("setoption name Hash value 2");
+        engine.handle_command("setoption name Clear Hash");
+        engine.handle_command("ucinewgame");
+        assert_eq!(0, engine.engine().tt.hashfull());
     }
 
     #[test]
@@ -1532,5 +2664,3 @@ mod tests {
     }
 
 }
-
-
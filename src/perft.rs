@@ -0,0 +1,85 @@
+use crate::pieces_logic::{self, Move};
+use crate::position::Position;
+
+use std::time::Instant;
+
+/// Counts the leaf nodes of the legal move tree `depth` plies below `position`.
+/// The position is made and unmade in place and comes back unchanged.
+pub fn perft(position: &mut Position, depth: u8) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = position.legal_moves();
+
+    // Bulk counting: the last ply doesn't need to be played out.
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for mv in moves.iter() {
+        let undo = position.make_move(mv);
+        nodes += perft(position, depth - 1);
+        position.unmake_move(mv, undo);
+    }
+    nodes
+}
+
+/// Perft split up by root move, to narrow down which move a wrong count comes from.
+pub fn perft_divide(position: &mut Position, depth: u8) -> Vec<(Move, u64)> {
+    let mut moves = position.legal_moves();
+    moves.sort();
+
+    moves
+        .into_iter()
+        .map(|mv| {
+            let undo = position.make_move(&mv);
+            let nodes = perft(position, depth.saturating_sub(1));
+            position.unmake_move(&mv, undo);
+            (mv, nodes)
+        })
+        .collect()
+}
+
+/// Prints one `e2e4: 20` line per root move and the total, in the format most engines use
+/// so the output can be diffed against a reference engine.
+pub fn print_perft_divide(position: &mut Position, depth: u8) {
+    let start = Instant::now();
+    let divide = perft_divide(position, depth);
+    let elapsed = start.elapsed();
+
+    let mut total = 0;
+    for (mv, nodes) in divide.iter() {
+        println!("{}: {}", pieces_logic::move_to_universal_chess_interface(mv), nodes);
+        total += nodes;
+    }
+
+    println!();
+    println!("Nodes searched: {}", total);
+    println!("Time: {} ms ({} nps)", elapsed.as_millis(), nodes_per_second(total, elapsed.as_secs_f64()));
+}
+
+pub fn print_perft(position: &mut Position, depth: u8) {
+    for d in 1..=depth {
+        let start = Instant::now();
+        let nodes = perft(position, d);
+        let elapsed = start.elapsed();
+
+        println!(
+            "perft({}) = {} in {} ms ({} nps)",
+            d,
+            nodes,
+            elapsed.as_millis(),
+            nodes_per_second(nodes, elapsed.as_secs_f64())
+        );
+    }
+}
+
+fn nodes_per_second(nodes: u64, seconds: f64) -> u64 {
+    if seconds > 0.0 {
+        (nodes as f64 / seconds) as u64
+    } else {
+        0
+    }
+}
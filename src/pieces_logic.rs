@@ -1,13 +1,26 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::cell::RefCell;
+use std::ops;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
 use crate::chess_board;
+use crate::eval_params::{self, EvalParams, Mobility};
+use crate::bitboard::{
+    adjacent_files, bishop_attacks, bit, file_mask, king_attacks, knight_attacks, pawn_attacks, queen_attacks,
+    rook_attacks, row_mask, square_coords, square_index, BitIter, Bitboard,
+};
+use crate::position::{CastlingRights, Position};
+use crate::time_manager::{SearchLimits, TimeManager};
 static NODES_EVALUATED: AtomicU64 = AtomicU64::new(0);
 
 pub fn nodes_reset() {
     NODES_EVALUATED.store(0, Ordering::Relaxed);
 }
 
-pub fn nodes_inc() {
-    NODES_EVALUATED.fetch_add(1, Ordering::Relaxed);
+pub fn nodes_add(nodes: u64) {
+    NODES_EVALUATED.fetch_add(nodes, Ordering::Relaxed);
 }
 
 pub fn nodes_get() -> u64 {
@@ -62,6 +75,17 @@ pub struct Move {
 }
 
 
+// Stands in for the king wherever material gets added up: more than everything else together.
+const KING_VALUE: i64 = 100_000;
+
+/// What a piece is worth in material: the midgame value from the evaluation weights.
+pub fn material_value(sym: Symbol) -> i64 {
+    match sym {
+        Symbol::King => KING_VALUE,
+        _ => eval_params::params().piece_value(sym).mg,
+    }
+}
+
 pub fn create_empty_piece(square: &(u8, u8)) -> Piece {
     Piece { color: Color::None, 
         symbol: Symbol::Empty,
@@ -75,7 +99,7 @@ pub fn place_pawn_on_board(board: &mut [[Piece; 8]; 8], square: &(u8, u8), color
         color: color,
         symbol: Symbol::Pawn,
         has_moved: false,
-        value: 100,
+        value: material_value(Symbol::Pawn),
         current_square: *square
     };
 
@@ -86,7 +110,7 @@ pub fn place_bishop_on_board(board: &mut [[Piece; 8]; 8], square: &(u8, u8), col
         color: color,
         symbol: Symbol::Bishop,
         has_moved: false,
-        value: 330,
+        value: material_value(Symbol::Bishop),
         current_square: *square
     };
     
@@ -97,7 +121,7 @@ pub fn place_knight_on_board(board: &mut [[Piece; 8]; 8], square: &(u8, u8), col
         color: color,
         symbol: Symbol::Knight,
         has_moved: false,
-        value: 320,
+        value: material_value(Symbol::Knight),
         current_square: *square
     };
 }
@@ -107,7 +131,7 @@ pub fn place_rook_on_board(board: &mut [[Piece; 8]; 8], square: &(u8, u8), color
         color: color,
         symbol: Symbol::Rook,
         has_moved: false,
-        value: 500,
+        value: material_value(Symbol::Rook),
         current_square: *square
     };
 }
@@ -117,7 +141,7 @@ pub fn place_queen_on_board(board: &mut [[Piece; 8]; 8], square: &(u8, u8), colo
         color: color,
         symbol: Symbol::Queen,
         has_moved: false,
-        value: 900,
+        value: material_value(Symbol::Queen),
         current_square: *square
     };
 }
@@ -127,7 +151,7 @@ pub fn place_king_on_board(board: &mut [[Piece; 8]; 8], square: &(u8, u8), color
         color: color,
         symbol: Symbol::King,
         has_moved: false,
-        value: 10000,
+        value: material_value(Symbol::King),
         current_square: *square
     };
 }
@@ -309,6 +333,29 @@ pub fn is_king_in_check(board: &[[Piece;8];8], color: Color) -> bool {
 
 
 
+// A pawn moving diagonally onto an empty square can only be an en passant capture.
+pub fn is_en_passant_capture(board: &[[Piece;8];8], piece_move: &Move) -> bool {
+    let from = piece_move.current_square;
+    let to = piece_move.destination_square;
+
+    board[from.0 as usize][from.1 as usize].symbol == Symbol::Pawn
+        && from.1 != to.1
+        && board[to.0 as usize][to.1 as usize].symbol == Symbol::Empty
+}
+
+/// Returns the square a pawn skipped over if this move is a double push, i.e. the square an
+/// enemy pawn may capture onto with en passant on the very next move.
+pub fn get_en_passant_square_after_move(board: &[[Piece;8];8], piece_move: &Move) -> Option<(u8, u8)> {
+    let from = piece_move.current_square;
+    let to = piece_move.destination_square;
+
+    if board[from.0 as usize][from.1 as usize].symbol == Symbol::Pawn && from.0.abs_diff(to.0) == 2 {
+        Some(((from.0 + to.0) / 2, from.1))
+    } else {
+        None
+    }
+}
+
 pub fn is_piece_pinned(board: &[[Piece;8];8], piece_move: &Move) -> bool {
     
     let from = piece_move.current_square;
@@ -328,7 +375,7 @@ pub fn is_piece_pinned(board: &[[Piece;8];8], piece_move: &Move) -> bool {
 // Moves 
 
 // Piece specific move functions
-pub fn get_legal_moves_for_pawn(board: &[[Piece;8];8], square: &(u8, u8)) -> Vec<Move> {
+pub fn get_legal_moves_for_pawn(board: &[[Piece;8];8], square: &(u8, u8), en_passant: Option<(u8, u8)>) -> Vec<Move> {
     
     let mut output: Vec<Move> = vec![];
         
@@ -347,11 +394,15 @@ pub fn get_legal_moves_for_pawn(board: &[[Piece;8];8], square: &(u8, u8)) -> Vec
             let up_move: Move = Move {current_square: *square, destination_square: (new_row as u8, square.1), castle: false, promotion: Promotion::NoPromotion};
             if !is_piece_pinned(&board, &up_move) {
                 output.push(up_move);
-                if (new_row + adder) >= 0 && (new_row + adder) < 8 {
-                    if board[(new_row + adder) as usize][square.1 as usize].symbol == Symbol::Empty && !piece_to_move.has_moved {
-                        let up_up_move: Move = Move { current_square: *square, destination_square: ((new_row + adder) as u8, square.1), castle: false, promotion: Promotion::NoPromotion};
-                        output.push(up_up_move); 
-                    }
+            }
+            // The double step is checked on its own: it can block a check the single step doesn't.
+            if (0..8).contains(&(new_row + adder))
+                && board[(new_row + adder) as usize][square.1 as usize].symbol == Symbol::Empty
+                && !piece_to_move.has_moved
+            {
+                let up_up_move: Move = Move { current_square: *square, destination_square: ((new_row + adder) as u8, square.1), castle: false, promotion: Promotion::NoPromotion};
+                if !is_piece_pinned(board, &up_up_move) {
+                    output.push(up_up_move);
                 }
             }
         }
@@ -376,6 +427,19 @@ pub fn get_legal_moves_for_pawn(board: &[[Piece;8];8], square: &(u8, u8)) -> Vec
                 }
             }
         }
+        // en passant
+        // is_piece_pinned plays the capture out (removing both pawns), so the case where the
+        // two pawns shield the king from a rook/queen on the same rank is caught as well.
+        if let Some(ep) = en_passant {
+            let captured = board[square.0 as usize][ep.1 as usize];
+            if ep.0 as i8 == new_row && ep.1.abs_diff(square.1) == 1
+                && captured.symbol == Symbol::Pawn && captured.color != piece_to_move.color {
+                let ep_move: Move = Move { current_square: *square, destination_square: ep, castle: false, promotion: Promotion::NoPromotion};
+                if !is_piece_pinned(board, &ep_move) {
+                    output.push(ep_move);
+                }
+            }
+        }
     }
 
     if new_row == 0 || new_row == 7 {
@@ -650,35 +714,33 @@ pub fn get_castling_moves(board: &[[Piece;8];8], color: Color) -> Vec<Move> {
 
     let mut output: Vec<Move> = vec![];
 
-    let row: usize = if color == Color::None {7} else {0};
+    let row: usize = if color == Color::White {7} else {0};
     let left_corner: Piece = board[row][0];
     let right_corner: Piece = board[row][7];
 
-    if king_piece.has_moved {
+    // Can't castle out of check either
+    if king_piece.has_moved || king != (row as u8, 4) || is_king_in_check(board, color) {
         return output;
     }
 
-    if left_corner.symbol != Symbol::Empty && left_corner.symbol == Symbol::Rook && !left_corner.has_moved {
-        
-        if  board[king.0 as usize][(king.1 - 1) as usize].symbol == Symbol::Empty &&
-            board[king.0 as usize][(king.1 - 2) as usize].symbol == Symbol::Empty {
-            // Check for pins 
-            if  !is_piece_pinned(&board, &Move { current_square: king, destination_square: (king.0, king.1 - 1), castle: false, promotion: Promotion::NoPromotion }) &&
-                !is_piece_pinned(&board, &Move { current_square: king, destination_square: (king.0, king.1 - 2), castle: false, promotion: Promotion::NoPromotion }) {
-                output.push(Move { current_square: king, destination_square: (king.0, king.1 - 2), castle: true, promotion: Promotion::NoPromotion });
-            }
+    if  left_corner.symbol == Symbol::Rook && left_corner.color == color && !left_corner.has_moved &&
+        board[king.0 as usize][(king.1 - 1) as usize].symbol == Symbol::Empty &&
+        board[king.0 as usize][(king.1 - 2) as usize].symbol == Symbol::Empty &&
+        board[king.0 as usize][(king.1 - 3) as usize].symbol == Symbol::Empty {
+        // Check for pins 
+        if  !is_piece_pinned(board, &Move { current_square: king, destination_square: (king.0, king.1 - 1), castle: false, promotion: Promotion::NoPromotion }) &&
+            !is_piece_pinned(board, &Move { current_square: king, destination_square: (king.0, king.1 - 2), castle: false, promotion: Promotion::NoPromotion }) {
+            output.push(Move { current_square: king, destination_square: (king.0, king.1 - 2), castle: true, promotion: Promotion::NoPromotion });
         }
     }
     
-    if right_corner.symbol != Symbol::Empty && right_corner.symbol == Symbol::Rook && !right_corner.has_moved {
-        
-        if  board[king.0 as usize][(king.1 + 1) as usize].symbol == Symbol::Empty &&
-            board[king.0 as usize][(king.1 + 2) as usize].symbol == Symbol::Empty {
-            // Check for pins 
-            if  !is_piece_pinned(&board, &Move { current_square: king, destination_square: (king.0, king.1 + 1), castle: false, promotion: Promotion::NoPromotion }) &&
-                !is_piece_pinned(&board, &Move { current_square: king, destination_square: (king.0, king.1 + 2), castle: false, promotion: Promotion::NoPromotion }) {
-                output.push(Move { current_square: king, destination_square: (king.0, king.1 + 2), castle: true, promotion: Promotion::NoPromotion });
-            }
+    if  right_corner.symbol == Symbol::Rook && right_corner.color == color && !right_corner.has_moved &&
+        board[king.0 as usize][(king.1 + 1) as usize].symbol == Symbol::Empty &&
+        board[king.0 as usize][(king.1 + 2) as usize].symbol == Symbol::Empty {
+        // Check for pins 
+        if  !is_piece_pinned(board, &Move { current_square: king, destination_square: (king.0, king.1 + 1), castle: false, promotion: Promotion::NoPromotion }) &&
+            !is_piece_pinned(board, &Move { current_square: king, destination_square: (king.0, king.1 + 2), castle: false, promotion: Promotion::NoPromotion }) {
+            output.push(Move { current_square: king, destination_square: (king.0, king.1 + 2), castle: true, promotion: Promotion::NoPromotion });
         }
     }
     output
@@ -692,6 +754,11 @@ pub fn make_move(board: &mut [[Piece; 8]; 8], move_: &Move) {
     
     let cur_sq = move_.current_square;
     let des_sq = move_.destination_square;
+
+    // The captured pawn sits beside the moving pawn, not on the destination square.
+    if is_en_passant_capture(board, move_) {
+        board[cur_sq.0 as usize][des_sq.1 as usize] = create_empty_piece(&(cur_sq.0, des_sq.1));
+    }
     
     board[des_sq.0 as usize][des_sq.1 as usize] = board[cur_sq.0 as usize][cur_sq.1 as usize];    
     board[des_sq.0 as usize][des_sq.1 as usize].current_square = des_sq;
@@ -725,12 +792,12 @@ pub fn make_move(board: &mut [[Piece; 8]; 8], move_: &Move) {
 
 
 // For bot moves
-pub fn find_all_legal_moves_for_a_piece(board: &[[Piece; 8]; 8], square: &(u8, u8)) -> Vec<Move> {
+pub fn find_all_legal_moves_for_a_piece(board: &[[Piece; 8]; 8], square: &(u8, u8), en_passant: Option<(u8, u8)>) -> Vec<Move> {
     
     let empty_vec: Vec<Move> = vec![];
 
     match board[square.0 as usize][square.1 as usize].symbol {
-        Symbol::Pawn => get_legal_moves_for_pawn(&board, &square),
+        Symbol::Pawn => get_legal_moves_for_pawn(&board, &square, en_passant),
         Symbol::Bishop => get_legal_moves_for_bishop(&board, &square),
         Symbol::Knight => get_legal_moves_for_knight(&board, &square),
         Symbol::Rook => get_legal_moves_for_rook(&board, &square),
@@ -741,7 +808,7 @@ pub fn find_all_legal_moves_for_a_piece(board: &[[Piece; 8]; 8], square: &(u8, u
 
 }
 
-pub fn get_all_legal_moves_for_this_turn(board: &[[Piece;8];8], side: Color) -> Vec<Move> {
+pub fn get_all_legal_moves_for_this_turn(board: &[[Piece;8];8], side: Color, en_passant: Option<(u8, u8)>) -> Vec<Move> {
 
     let mut output: Vec<Move> = vec![];
     
@@ -749,7 +816,7 @@ pub fn get_all_legal_moves_for_this_turn(board: &[[Piece;8];8], side: Color) ->
         for y in 0..8 {
             let board_square: Piece = board[x][y];
             if board_square.color == side && board_square.symbol != Symbol::Empty { 
-                output.extend(find_all_legal_moves_for_a_piece(&board, &(x as u8, y as u8)));
+                output.extend(find_all_legal_moves_for_a_piece(&board, &(x as u8, y as u8), en_passant));
             }
         }
     } 
@@ -759,18 +826,18 @@ pub fn get_all_legal_moves_for_this_turn(board: &[[Piece;8];8], side: Color) ->
 
 
 // Game States
-pub fn is_checkmate(board: &[[Piece; 8]; 8], side: Color) -> bool {
+pub fn is_checkmate(board: &[[Piece; 8]; 8], side: Color, en_passant: Option<(u8, u8)>) -> bool {
 
     let output: Vec<Move> = vec![];
 
-    is_king_in_check(&board, side) && get_all_legal_moves_for_this_turn(&board, side) == output
+    is_king_in_check(&board, side) && get_all_legal_moves_for_this_turn(&board, side, en_passant) == output
 }
 
-pub fn is_stalemate(board: &[[Piece; 8]; 8], side: Color) -> bool {
+pub fn is_stalemate(board: &[[Piece; 8]; 8], side: Color, en_passant: Option<(u8, u8)>) -> bool {
 
     let output: Vec<Move> = vec![];
 
-    !is_king_in_check(&board, side) && get_all_legal_moves_for_this_turn(&board, side) == output
+    !is_king_in_check(&board, side) && get_all_legal_moves_for_this_turn(&board, side, en_passant) == output
 }
 
 pub fn is_insufficient_material(board: &[[Piece;8];8]) -> bool {
@@ -813,7 +880,7 @@ pub fn is_insufficient_material(board: &[[Piece;8];8]) -> bool {
 // ============================================================================
 
 #[inline]
-fn opponent(side: Color) -> Color {
+pub fn opponent(side: Color) -> Color {
     match side {
         Color::White => Color::Black,
         Color::Black => Color::White,
@@ -821,11 +888,44 @@ fn opponent(side: Color) -> Color {
     }
 }
 
+// Score for being mated at the root; every ply further away scores one less.
+pub const MATE_SCORE: i64 = 1_000_000_000;
+
+// Anything this close to MATE_SCORE is a forced mate rather than a material score.
+pub const MATE_THRESHOLD: i64 = MATE_SCORE - 1_000;
+
+// Bound no score reaches, for opening the search window.
+pub const INFINITY: i64 = MATE_SCORE + 1;
+
+// Mate scores count plies from the root, but the TT has to hold them counted from the
+// entry's own position, so it can be found again at any ply.
+#[inline]
+fn score_to_tt(score: i64, ply: usize) -> i64 {
+    if score >= MATE_THRESHOLD {
+        score + ply as i64
+    } else if score <= -MATE_THRESHOLD {
+        score - ply as i64
+    } else {
+        score
+    }
+}
+
+#[inline]
+fn score_from_tt(score: i64, ply: usize) -> i64 {
+    if score >= MATE_THRESHOLD {
+        score - ply as i64
+    } else if score <= -MATE_THRESHOLD {
+        score + ply as i64
+    } else {
+        score
+    }
+}
+
 // =========================
 // TRANSPOSITION TABLE
 // =========================
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TtFlag {
     Exact,
     LowerBound,
@@ -842,9 +942,35 @@ pub struct TtEntry {
     pub best_valid: bool,
 }
 
+/// One slot, shared by every search thread without locks. The entry is packed into `data`
+/// and `check` holds the key XORed with it, so a slot torn by two threads writing at once
+/// no longer matches its key and simply reads as a miss.
+#[derive(Default)]
+struct TtSlot {
+    check: AtomicU64,
+    data: AtomicU64,
+}
+
+// Four slots fill one cache line, so a probe costs a single memory access.
+const BUCKET_SLOTS: usize = 4;
+
+#[derive(Default)]
+#[repr(align(64))]
+struct TtBucket {
+    slots: [TtSlot; BUCKET_SLOTS],
+}
+
+// Searches are numbered modulo this so entries left over from earlier ones can be told apart.
+const GENERATIONS: u8 = 32;
+
+/// Size of a table nobody asked to size, in megabytes.
+pub const DEFAULT_HASH_MB: usize = 16;
+
+/// Shared by all searches of one engine, so what was learned on one move is still there on
+/// the next. Entries left by earlier searches are the first to go when a bucket is full.
 pub struct TranspositionTable {
-    mask: usize,
-    table: Vec<TtEntry>,
+    buckets: Vec<TtBucket>,
+    generation: u8,
 }
 
 #[inline]
@@ -857,41 +983,144 @@ fn empty_move() -> Move {
     }
 }
 
+const PROMOTIONS: [Promotion; 5] = [
+    Promotion::Queen,
+    Promotion::Rook,
+    Promotion::Bishop,
+    Promotion::Knight,
+    Promotion::NoPromotion,
+];
+
+// Bits 0-31 value, 32-39 depth, 40-41 flag, 42-47 from, 48-53 to, 54 castle,
+// 55-57 promotion, 58 best_valid, 59-63 generation. Scores, mates included, fit in an i32.
+#[inline]
+fn pack_entry(entry: &TtEntry, generation: u8) -> u64 {
+    let promotion = PROMOTIONS.iter().position(|&p| p == entry.best.promotion).unwrap_or(4);
+    (entry.value as i32 as u32 as u64)
+        | (entry.depth as u64) << 32
+        | (entry.flag as u64) << 40
+        | (square_index(entry.best.current_square) as u64) << 42
+        | (square_index(entry.best.destination_square) as u64) << 48
+        | (entry.best.castle as u64) << 54
+        | (promotion as u64) << 55
+        | (entry.best_valid as u64) << 58
+        | (generation as u64) << 59
+}
+
+#[inline]
+fn unpack_entry(key: u64, data: u64) -> TtEntry {
+    let flag = match (data >> 40) & 3 {
+        0 => TtFlag::Exact,
+        1 => TtFlag::LowerBound,
+        _ => TtFlag::UpperBound,
+    };
+    TtEntry {
+        key,
+        depth: (data >> 32) as u8,
+        value: data as u32 as i32 as i64,
+        flag,
+        best: Move {
+            current_square: square_coords(((data >> 42) & 63) as usize),
+            destination_square: square_coords(((data >> 48) & 63) as usize),
+            castle: (data >> 54) & 1 != 0,
+            promotion: PROMOTIONS[(((data >> 55) & 7) as usize).min(4)],
+        },
+        best_valid: (data >> 58) & 1 != 0,
+    }
+}
+
 impl TranspositionTable {
-    pub fn new_pow2(entries_pow2: usize) -> Self {
-        let size = 1usize << entries_pow2;
-        let empty = TtEntry {
-            key: 0,
-            depth: 0,
-            value: 0,
-            flag: TtFlag::Exact,
-            best: empty_move(),
-            best_valid: false,
-        };
+    pub fn new(megabytes: usize) -> Self {
+        let buckets = (megabytes.max(1) << 20) / std::mem::size_of::<TtBucket>();
         Self {
-            mask: size - 1,
-            table: vec![empty; size],
+            buckets: (0..buckets).map(|_| TtBucket::default()).collect(),
+            generation: 0,
         }
     }
 
+    /// Empties the table, for a new game.
+    pub fn clear(&mut self) {
+        self.buckets.iter_mut().for_each(|bucket| *bucket = TtBucket::default());
+        self.generation = 0;
+    }
+
+    /// Called before every search; what earlier ones stored ages from then on.
+    pub fn new_search(&mut self) {
+        self.generation = (self.generation + 1) % GENERATIONS;
+    }
+
+    /// Permille of the table filled by the current search, estimated from its first buckets.
+    pub fn hashfull(&self) -> u32 {
+        let sample = &self.buckets[..self.buckets.len().min(1000 / BUCKET_SLOTS)];
+        let used = sample
+            .iter()
+            .flat_map(|bucket| &bucket.slots)
+            .filter(|slot| {
+                let data = slot.data.load(Ordering::Relaxed);
+                data != 0 && (data >> 59) as u8 == self.generation
+            })
+            .count();
+        (used * 1000 / (sample.len() * BUCKET_SLOTS)) as u32
+    }
+
+    #[inline]
+    fn bucket(&self, key: u64) -> &TtBucket {
+        // Maps the key onto any table size, not just powers of two.
+        &self.buckets[((key as u128 * self.buckets.len() as u128) >> 64) as usize]
+    }
+
+    #[inline]
+    fn read(slot: &TtSlot) -> (u64, u64) {
+        let data = slot.data.load(Ordering::Relaxed);
+        (slot.check.load(Ordering::Relaxed) ^ data, data)
+    }
+
     #[inline]
     pub fn probe(&self, key: u64) -> Option<TtEntry> {
-        let e = self.table[(key as usize) & self.mask];
-        if e.key == key && e.key != 0 {
-            Some(e)
-        } else {
-            None
+        if key == 0 {
+            return None;
         }
+        self.bucket(key).slots.iter().find_map(|slot| {
+            let (stored, data) = Self::read(slot);
+            (stored == key).then(|| unpack_entry(key, data))
+        })
     }
 
+    // How much a slot is worth keeping: deep results are, stale ones much less so.
     #[inline]
-    pub fn store(&mut self, entry: TtEntry) {
-        let idx = (entry.key as usize) & self.mask;
-        let cur = self.table[idx];
+    fn worth(&self, data: u64) -> i32 {
+        let age = (GENERATIONS + self.generation - (data >> 59) as u8) % GENERATIONS;
+        ((data >> 32) as u8) as i32 - 8 * age as i32
+    }
 
-        if cur.key == 0 || entry.depth >= cur.depth {
-            self.table[idx] = entry;
+    /// Stores over the entry for the same position if the new one searched at least as deep
+    /// (or the old one is from an earlier search), else over the least valuable slot of the bucket.
+    #[inline]
+    pub fn store(&self, entry: TtEntry) {
+        let bucket = self.bucket(entry.key);
+        let mut victim = &bucket.slots[0];
+        let mut victim_worth = i32::MAX;
+
+        for slot in &bucket.slots {
+            let (stored, data) = Self::read(slot);
+            if stored == entry.key {
+                let same_search = (data >> 59) as u8 == self.generation;
+                if same_search && ((data >> 32) as u8) > entry.depth {
+                    return;
+                }
+                victim = slot;
+                break;
+            }
+            let worth = if data == 0 { i32::MIN } else { self.worth(data) };
+            if worth < victim_worth {
+                victim = slot;
+                victim_worth = worth;
+            }
         }
+
+        let data = pack_entry(&entry, self.generation);
+        victim.check.store(entry.key ^ data, Ordering::Relaxed);
+        victim.data.store(data, Ordering::Relaxed);
     }
 }
 
@@ -902,6 +1131,8 @@ impl TranspositionTable {
 pub struct Zobrist {
     piece: [[u64; 24]; 64],
     side_to_move: u64,
+    en_passant: [u64; 8],
+    castling: [u64; 4],
 }
 
 #[inline]
@@ -928,7 +1159,62 @@ impl Zobrist {
         seed = splitmix64(seed);
         let side_to_move = seed;
 
-        Self { piece, side_to_move }
+        let mut en_passant = [0u64; 8];
+        for key in en_passant.iter_mut() {
+            seed = splitmix64(seed);
+            *key = seed;
+        }
+
+        let mut castling = [0u64; 4];
+        for key in castling.iter_mut() {
+            seed = splitmix64(seed);
+            *key = seed;
+        }
+
+        Self { piece, side_to_move, en_passant, castling }
+    }
+
+    /// Key for a piece on a square index (row * 8 + col), as used by the bitboard position.
+    #[inline(always)]
+    pub fn piece_key(&self, square: usize, color: Color, symbol: Symbol) -> u64 {
+        let mut piece = create_empty_piece(&(0, 0));
+        piece.symbol = symbol;
+        piece.color = color;
+        piece_index(piece).map_or(0, |pi| self.piece[square][pi])
+    }
+
+    #[inline(always)]
+    pub fn side_key(&self) -> u64 {
+        self.side_to_move
+    }
+
+    #[inline(always)]
+    pub fn en_passant_key(&self, file: u8) -> u64 {
+        self.en_passant[file as usize]
+    }
+
+    pub fn castling_key(&self, rights: &CastlingRights) -> u64 {
+        let mut h = 0;
+        for (allowed, key) in [
+            rights.white_kingside,
+            rights.white_queenside,
+            rights.black_kingside,
+            rights.black_queenside,
+        ]
+        .iter()
+        .zip(self.castling.iter())
+        {
+            if *allowed {
+                h ^= key;
+            }
+        }
+        h
+    }
+}
+
+impl Default for Zobrist {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -959,223 +1245,641 @@ fn piece_index(p: Piece) -> Option<usize> {
     Some(((s * 2 + c) * 2 + m) as usize)
 }
 
+// =========================
+// SOFT MOVE ORDERING
+// =========================
+
 #[inline]
-pub fn zobrist_hash(board: &[[Piece; 8]; 8], side: Color, z: &Zobrist) -> u64 {
-    let mut h = 0u64;
-    for r in 0..8 {
-        for c in 0..8 {
-            if let Some(pi) = piece_index(board[r][c]) {
-                h ^= z.piece[r * 8 + c][pi];
-            }
-        }
+fn val(sym: Symbol) -> i32 {
+    material_value(sym) as i32
+}
+
+#[inline]
+fn promotion_value(promotion: Promotion) -> i32 {
+    match promotion {
+        Promotion::Queen => val(Symbol::Queen),
+        Promotion::Rook => val(Symbol::Rook),
+        Promotion::Bishop => val(Symbol::Bishop),
+        Promotion::Knight => val(Symbol::Knight),
+        Promotion::NoPromotion => 0,
+    }
+}
+
+// Cheapest first, so every exchange is fought with the least valuable attacker.
+const SEE_ORDER: [Symbol; 6] = [Symbol::Pawn, Symbol::Knight, Symbol::Bishop, Symbol::Rook, Symbol::Queen, Symbol::King];
+
+/// Static exchange evaluation: the material `mv` wins (or loses, if negative) once both sides
+/// have made every recapture on the destination square that pays off for them. Sliders lined up
+/// behind the pieces taking part join in as soon as their way opens.
+pub fn see(position: &Position, mv: &Move) -> i32 {
+    let to = square_index(mv.destination_square);
+    let from = square_index(mv.current_square);
+    let mut occupied = position.occupied() & !bit(from);
+
+    let mut gain = [0i32; 32];
+    gain[0] = val(position.symbol_at(mv.destination_square));
+    if position.is_en_passant_capture(mv) {
+        gain[0] = val(Symbol::Pawn);
+        occupied &= !bit(square_index((mv.current_square.0, mv.destination_square.1)));
+    }
+
+    // Value of the piece standing on the square, which the next capture wins.
+    let mut on_square = val(position.symbol_at(mv.current_square));
+    if mv.promotion != Promotion::NoPromotion {
+        gain[0] += promotion_value(mv.promotion) - val(Symbol::Pawn);
+        on_square = promotion_value(mv.promotion);
+    }
+
+    let mut side = opponent(position.color_at(mv.current_square));
+    let mut d = 0;
+
+    while d + 1 < gain.len() {
+        let attackers = position.attackers_to(to, occupied) & position.occupied_by(side);
+        let Some((square, symbol)) = SEE_ORDER.iter().find_map(|&symbol| {
+            let candidates = attackers & position.pieces(side, symbol);
+            (candidates != 0).then(|| (candidates.trailing_zeros() as usize, symbol))
+        }) else {
+            break;
+        };
+
+        d += 1;
+        gain[d] = on_square - gain[d - 1];
+        on_square = val(symbol);
+        occupied &= !bit(square);
+        side = opponent(side);
     }
-    if side == Color::Black {
-        h ^= z.side_to_move;
+
+    // Either side may stop recapturing whenever carrying on would cost it.
+    while d > 0 {
+        gain[d - 1] = -(-gain[d - 1]).max(gain[d]);
+        d -= 1;
     }
-    h
+    gain[0]
 }
 
 #[inline]
-pub fn hash_after_move(
-    mut h: u64,
-    board: &[[Piece; 8]; 8],
-    mv: &Move,
-    side: Color,
-    z: &Zobrist,
-) -> u64 {
-    let (fr, fc) = mv.current_square;
-    let (tr, tc) = mv.destination_square;
+fn is_tactical(node: &Position, mv: &Move) -> bool {
+    mv.castle || mv.promotion != Promotion::NoPromotion || node.is_capture(mv)
+}
+
+#[inline]
+fn soft_score(node: &Position, mv: &Move) -> i32 {
+    let mover = node.symbol_at(mv.current_square);
+    let target = node.symbol_at(mv.destination_square);
 
-    let from = fr as usize * 8 + fc as usize;
-    let to = tr as usize * 8 + tc as usize;
+    let mut s = 0;
 
-    let mut moving = board[fr as usize][fc as usize];
-    let target = board[tr as usize][tc as usize];
+    if mv.promotion != Promotion::NoPromotion {
+        s += 20_000;
+    }
 
-    if let Some(pi) = piece_index(moving) {
-        h ^= z.piece[from][pi];
+    if target != Symbol::Empty {
+        s += 10_000 + val(target) - val(mover) / 10;
+    } else if node.is_en_passant_capture(mv) {
+        s += 10_000 + val(Symbol::Pawn) - val(mover) / 10;
     }
 
-    if let Some(pi) = piece_index(target) {
-        h ^= z.piece[to][pi];
+    if mv.castle {
+        s += 2_000;
     }
 
-    if moving.symbol == Symbol::Pawn {
-        match mv.promotion {
-            Promotion::Queen => moving.symbol = Symbol::Queen,
-            Promotion::Rook => moving.symbol = Symbol::Rook,
-            Promotion::Bishop => moving.symbol = Symbol::Bishop,
-            Promotion::Knight => moving.symbol = Symbol::Knight,
-            Promotion::NoPromotion => {}
+    s
+}
+
+/// Winning and even tactical moves first, then the quiet moves by `quiet_score`, then the
+/// captures that lose material, least bad first.
+#[inline]
+pub fn order_moves_soft_in_negamax(node: &Position, moves: &mut [Move], quiet_score: impl Fn(&Move) -> i32) {
+    moves.sort_by_cached_key(|m| {
+        if !is_tactical(node, m) {
+            return (1, -quiet_score(m));
+        }
+        if node.is_capture(m) {
+            let exchange = see(node, m);
+            if exchange < 0 {
+                return (2, -exchange);
+            }
         }
+        (0, -soft_score(node, m))
+    });
+}
+
+// =========================
+// QUIET MOVE ORDERING
+// =========================
+
+/// Deepest ply the per-ply tables keep track of.
+pub const MAX_PLY: usize = 128;
+
+// History scores stay within +-MAX_HISTORY, below the killer and counter-move bonuses.
+const MAX_HISTORY: i32 = 1 << 20;
+const KILLER_SCORE: i32 = 1 << 23;
+const COUNTER_SCORE: i32 = 1 << 22;
+
+/// What the search learned about quiet moves from earlier beta cutoffs: two killer moves per
+/// ply, a butterfly history indexed by side, from- and to-square, and the move that refuted
+/// each previous move.
+pub struct History {
+    killers: [[Option<Move>; 2]; MAX_PLY],
+    butterfly: [[[i32; 64]; 64]; 2],
+    counters: [[Option<Move>; 64]; 64],
+}
+
+#[inline]
+fn side_index(side: Color) -> usize {
+    if side == Color::White { 0 } else { 1 }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    moving.has_moved = true;
+impl History {
+    pub fn new() -> Self {
+        Self {
+            killers: [[None; 2]; MAX_PLY],
+            butterfly: [[[0; 64]; 64]; 2],
+            counters: [[None; 64]; 64],
+        }
+    }
 
-    if let Some(pi) = piece_index(moving) {
-        h ^= z.piece[to][pi];
+    /// Ordering score of a quiet move at `ply`, played in reply to `previous`.
+    pub fn quiet_score(&self, side: Color, mv: &Move, ply: usize, previous: Option<Move>) -> i32 {
+        if let Some(killers) = self.killers.get(ply) {
+            if killers[0] == Some(*mv) {
+                return KILLER_SCORE + 1;
+            }
+            if killers[1] == Some(*mv) {
+                return KILLER_SCORE;
+            }
+        }
+        if let Some(prev) = previous
+            && self.counters[square_index(prev.current_square)][square_index(prev.destination_square)] == Some(*mv)
+        {
+            return COUNTER_SCORE;
+        }
+        self.butterfly[side_index(side)][square_index(mv.current_square)][square_index(mv.destination_square)]
     }
 
-    if mv.castle {
-        let row = fr as usize;
-        let rook_from = if tc > fc { 7 } else { 0 };
-        let rook_to = if tc > fc { 5 } else { 3 };
+    /// Records that quiet `mv` caused a beta cutoff at `ply`, after `tried` had failed to.
+    pub fn on_cutoff(&mut self, side: Color, mv: &Move, depth: u8, ply: usize, previous: Option<Move>, tried: &[Move]) {
+        if let Some(killers) = self.killers.get_mut(ply)
+            && killers[0] != Some(*mv)
+        {
+            killers[1] = killers[0];
+            killers[0] = Some(*mv);
+        }
 
-        let rook = board[row][rook_from];
-        if let Some(pi) = piece_index(rook) {
-            h ^= z.piece[row * 8 + rook_from][pi];
+        if let Some(prev) = previous {
+            self.counters[square_index(prev.current_square)][square_index(prev.destination_square)] = Some(*mv);
         }
 
-        let mut rook2 = rook;
-        rook2.has_moved = true;
-        if let Some(pi) = piece_index(rook2) {
-            h ^= z.piece[row * 8 + rook_to][pi];
+        let bonus = (depth as i32 * depth as i32).min(MAX_HISTORY);
+        self.add_history(side, mv, bonus);
+        for other in tried {
+            self.add_history(side, other, -bonus);
         }
     }
 
-    h ^ z.side_to_move
+    // Moves the entry towards +-MAX_HISTORY, by less the closer it already is.
+    fn add_history(&mut self, side: Color, mv: &Move, bonus: i32) {
+        let entry =
+            &mut self.butterfly[side_index(side)][square_index(mv.current_square)][square_index(mv.destination_square)];
+        *entry += bonus - *entry * bonus.abs() / MAX_HISTORY;
+    }
 }
 
 // =========================
-// SOFT MOVE ORDERING
+// NEGAMAX + TT + PVS
 // =========================
 
-#[inline]
-fn val(sym: Symbol) -> i32 {
-    match sym {
-        Symbol::Pawn => 100,
-        Symbol::Knight => 320,
-        Symbol::Bishop => 330,
-        Symbol::Rook => 500,
-        Symbol::Queen => 900,
-        Symbol::King => 100_000,
-        _ => 0,
+/// Triangular PV table: for every ply the best line found from there, built bottom-up
+/// by prefixing each improving move to the line of the ply below.
+pub struct PvTable {
+    lines: Vec<Vec<Move>>,
+}
+
+impl Default for PvTable {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-#[inline]
-fn is_tactical(board: &[[Piece; 8]; 8], mv: &Move) -> bool {
-    mv.castle
-        || mv.promotion != Promotion::NoPromotion
-        || board[mv.destination_square.0 as usize][mv.destination_square.1 as usize].symbol
-            != Symbol::Empty
+impl PvTable {
+    pub fn new() -> Self {
+        Self { lines: (0..=MAX_PLY).map(|_| Vec::with_capacity(MAX_PLY)).collect() }
+    }
+
+    /// Called on entering a node, so a line left over from a sibling isn't picked up.
+    #[inline]
+    pub fn clear(&mut self, ply: usize) {
+        if let Some(line) = self.lines.get_mut(ply) {
+            line.clear();
+        }
+    }
+
+    /// `mv` is the new best move at `ply`: its line becomes `mv` followed by the child's line.
+    #[inline]
+    pub fn update(&mut self, ply: usize, mv: Move) {
+        if ply >= MAX_PLY {
+            return;
+        }
+        let (head, tail) = self.lines.split_at_mut(ply + 1);
+        let line = &mut head[ply];
+        line.clear();
+        line.push(mv);
+        line.extend_from_slice(&tail[0]);
+    }
+
+    pub fn line(&self, ply: usize) -> &[Move] {
+        self.lines.get(ply).map_or(&[], |line| line.as_slice())
+    }
 }
 
-#[inline]
-fn soft_score(board: &[[Piece; 8]; 8], mv: &Move) -> i32 {
-    let from = mv.current_square;
-    let to = mv.destination_square;
+/// How the search runs. The selective parts can each be switched off on their own to
+/// measure what they are worth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub null_move: bool,
+    pub late_move_reductions: bool,
+    pub reverse_futility: bool,
+    pub futility: bool,
+    /// How many of the best root moves get a line of their own, for analysis.
+    pub multi_pv: usize,
+    /// Threads searching the root together, sharing the transposition table (Lazy SMP).
+    pub threads: usize,
+}
 
-    let mover = board[from.0 as usize][from.1 as usize];
-    let target = board[to.0 as usize][to.1 as usize];
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self { null_move: true, late_move_reductions: true, reverse_futility: true, futility: true, multi_pv: 1, threads: 1 }
+    }
+}
 
-    let mut s = 0;
+// Reverse futility: a node this close to the leaves whose static evaluation beats beta
+// by this much per ply is assumed to fail high.
+const REVERSE_FUTILITY_DEPTH: u8 = 3;
+const REVERSE_FUTILITY_MARGIN: i64 = 120;
+
+// Futility: quiet moves that can't lift the static evaluation up to alpha within the
+// margin for the remaining depth aren't searched.
+const FUTILITY_MARGINS: [i64; 4] = [0, 200, 350, 500];
+
+const NULL_MOVE_MIN_DEPTH: u8 = 3;
+
+// Late move reductions kick in after this many moves, at this depth or more.
+const LMR_MIN_MOVES: usize = 3;
+const LMR_MIN_DEPTH: u8 = 3;
+
+/// What outlives a single search: the keys and the transposition table, which keeps what
+/// was learned from one move to the next until the game ends. All threads of a search share it.
+pub struct Engine<E = HandcraftedEvaluator> {
+    pub z: Zobrist,
+    pub tt: TranspositionTable,
+    pub evaluator: E,
+    // Nodes visited by all threads of the running search.
+    nodes: AtomicU64,
+}
 
-    if mv.promotion != Promotion::NoPromotion {
-        s += 20_000;
+impl Engine {
+    pub fn new(hash_megabytes: usize) -> Self {
+        Self::with_evaluator(hash_megabytes, HandcraftedEvaluator)
     }
+}
 
-    if target.symbol != Symbol::Empty {
-        s += 10_000 + val(target.symbol) - val(mover.symbol) / 10;
+impl<E: Evaluator> Engine<E> {
+    pub fn with_evaluator(hash_megabytes: usize, evaluator: E) -> Self {
+        Self { z: Zobrist::new(), tt: TranspositionTable::new(hash_megabytes), evaluator, nodes: AtomicU64::new(0) }
     }
 
-    if mv.castle {
-        s += 2_000;
+    pub fn resize_hash(&mut self, megabytes: usize) {
+        self.tt = TranspositionTable::new(megabytes);
     }
 
-    s
+    /// Forgets everything about the previous game.
+    pub fn new_game(&mut self) {
+        self.tt.clear();
+    }
+
+    /// Iterative deepening within `limits`. The time manager decides whether another iteration
+    /// is worth starting and `on_iteration` gets called once each iteration is complete, with
+    /// `options.multi_pv` lines if more than the best one was asked for. Running
+    /// out of time or nodes, or raising `stop`, aborts the running iteration; the best move of the
+    /// last completed one is returned.
+    ///
+    /// With more than one of `options.threads`, helper threads search the same root alongside,
+    /// each on its own copy of the position. They only share the transposition table, so what one
+    /// thread has found cuts the others' searches short, and they finish when this one does.
+    pub fn search(
+        &mut self,
+        node: &Position,
+        limits: &SearchLimits,
+        options: &SearchOptions,
+        stop: &AtomicBool,
+        mut on_iteration: impl FnMut(&SearchInfo),
+    ) -> Move {
+        self.tt.new_search();
+        self.nodes.store(0, Ordering::Relaxed);
+        let engine = &*self;
+        // Raised when the main thread is done, which ends the helpers' searches too.
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            for id in 1..options.threads.max(1) {
+                let done = &done;
+                scope.spawn(move || {
+                    let mut ctx = SearchContext::new(&SearchLimits::default(), options, engine, done);
+                    helper_search(*node, id, limits.depth, &mut ctx);
+                    ctx.flush_nodes();
+                });
+            }
+
+            let mut ctx = SearchContext::new(limits, options, engine, stop);
+            let best = iterative_deepening(*node, limits, &mut ctx, &mut on_iteration);
+            ctx.flush_nodes();
+            done.store(true, Ordering::Relaxed);
+            best
+        })
+    }
 }
 
-#[inline]
-pub fn order_moves_soft_in_negamax(board: &[[Piece; 8]; 8], moves: &mut Vec<Move>) {
-    let mut i = 0;
-    for j in 0..moves.len() {
-        if is_tactical(board, &moves[j]) {
-            moves.swap(i, j);
-            i += 1;
-        }
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(DEFAULT_HASH_MB)
     }
-    moves[..i].sort_unstable_by(|a, b| soft_score(board, b).cmp(&soft_score(board, a)));
 }
 
-// =========================
-// NEGAMAX + TT + PVS
-// =========================
+/// State shared by every node one thread searches. Generic over the evaluator, so the
+/// evaluation call at every node is resolved at compile time.
+pub struct SearchContext<'a, E: Evaluator> {
+    pub z: &'a Zobrist,
+    pub tt: &'a TranspositionTable,
+    pub evaluator: &'a E,
+    pub time: TimeManager,
+    pub history: History,
+    pub pv: PvTable,
+    pub options: SearchOptions,
+    /// The move played at each ply (None for a null move) on the way to the current node.
+    pub played: [Option<Move>; MAX_PLY],
+    /// Nodes visited by this thread.
+    pub nodes: u64,
+    // How many of them have been added to the shared count already.
+    flushed: u64,
+    shared_nodes: &'a AtomicU64,
+    stop: &'a AtomicBool,
+    stopped: bool,
+}
+
+// Reading the clock on every node would cost more than it's worth, and so would having
+// every thread bump the shared node count on every node.
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+impl<'a, E: Evaluator> SearchContext<'a, E> {
+    pub fn new(
+        limits: &SearchLimits,
+        options: &SearchOptions,
+        engine: &'a Engine<E>,
+        stop: &'a AtomicBool,
+    ) -> Self {
+        Self {
+            z: &engine.z,
+            tt: &engine.tt,
+            evaluator: &engine.evaluator,
+            time: TimeManager::new(limits),
+            history: History::new(),
+            pv: PvTable::new(),
+            options: *options,
+            played: [None; MAX_PLY],
+            nodes: 0,
+            flushed: 0,
+            shared_nodes: &engine.nodes,
+            stop,
+            stopped: false,
+        }
+    }
+
+    #[inline]
+    fn count_node(&mut self) {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(TIME_CHECK_INTERVAL) {
+            self.flush_nodes();
+        }
+    }
+
+    /// Adds the nodes not counted yet to the shared count and the global one.
+    pub fn flush_nodes(&mut self) {
+        let pending = self.nodes - self.flushed;
+        self.shared_nodes.fetch_add(pending, Ordering::Relaxed);
+        nodes_add(pending);
+        self.flushed = self.nodes;
+    }
+
+    /// Nodes visited by all threads of the search, as far as this one knows. Exact with a
+    /// single thread; the others only report every `TIME_CHECK_INTERVAL` nodes.
+    pub fn total_nodes(&self) -> u64 {
+        self.shared_nodes.load(Ordering::Relaxed) + self.nodes - self.flushed
+    }
+
+    /// True once the stop flag has been raised or a time or node limit was hit. Sticky,
+    /// so a search that saw it once unwinds all the way up even if the flag gets cleared meanwhile.
+    #[inline]
+    pub fn stopped(&mut self) -> bool {
+        if !self.stopped
+            && (self.stop.load(Ordering::Relaxed)
+                || self.time.out_of_nodes(self.total_nodes())
+                || (self.nodes.is_multiple_of(TIME_CHECK_INTERVAL) && self.time.out_of_time()))
+        {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+}
 
-pub fn negamax_tt_pvs(
-    node: &[[Piece; 8]; 8],
+/// Scores returned after the search was stopped are meaningless and must not be used
+/// (or stored in the TT) by the caller.
+pub fn negamax_tt_pvs<E: Evaluator>(
+    node: &mut Position,
     depth: u8,
+    ply: usize,
     mut alpha: i64,
-    beta: i64,
-    side: Color,
+    mut beta: i64,
     hash: u64,
-    z: &Zobrist,
-    tt: &mut TranspositionTable,
+    ctx: &mut SearchContext<E>,
 ) -> i64 {
-    nodes_inc();
+    if ctx.stopped() {
+        return 0;
+    }
 
-    let alpha_orig = alpha;
+    ctx.pv.clear(ply);
 
-    if let Some(e) = tt.probe(hash) {
-        if e.depth >= depth {
-            match e.flag {
-                TtFlag::Exact => return e.value,
-                TtFlag::LowerBound if e.value >= beta => return e.value,
-                TtFlag::UpperBound if e.value <= alpha => return e.value,
-                _ => {}
-            }
-        }
+    if depth == 0 {
+        return quiescence(node, ply, alpha, beta, ctx);
     }
 
-    if depth == 0 {
-        return if side == Color::White {
-            evaluate(node)
-        } else {
-            -evaluate(node)
-        };
+    if ply >= MAX_PLY {
+        return evaluate_relative(node, ctx.evaluator);
+    }
+
+    ctx.count_node();
+
+    // Mate distance pruning: no line from here can mate faster than mating right now,
+    // and a shorter mate was already found if alpha is past that.
+    alpha = alpha.max(-(MATE_SCORE - ply as i64));
+    beta = beta.min(MATE_SCORE - ply as i64 - 1);
+    if alpha >= beta {
+        return alpha;
+    }
+
+    let alpha_orig = alpha;
+    // Only null-window nodes get pruned, the principal variation is searched in full.
+    let pv_node = beta - alpha > 1;
+
+    // PV nodes don't take cutoffs from the TT, they would cut the PV short.
+    if !pv_node
+        && let Some(e) = ctx.tt.probe(hash)
+        && e.depth >= depth
+    {
+        let value = score_from_tt(e.value, ply);
+        match e.flag {
+            TtFlag::Exact => return value,
+            TtFlag::LowerBound if value >= beta => return value,
+            TtFlag::UpperBound if value <= alpha => return value,
+            _ => {}
+        }
     }
 
-    let mut moves = get_all_legal_moves_for_this_turn(node, side);
+    let mut moves = node.legal_moves();
+    let in_check = node.is_in_check();
 
     if moves.is_empty() {
-        return if is_king_in_check(node, side) {
-            -(1_000_000_000 - depth as i64)
+        return if in_check {
+            -(MATE_SCORE - ply as i64)
         } else {
             0
         };
     }
 
-    if let Some(e) = tt.probe(hash) {
-        if e.best_valid {
-            if let Some(p) = moves.iter().position(|m| *m == e.best) {
-                moves.swap(0, p);
-            }
+    let previous = ply.checked_sub(1).and_then(|p| ctx.played.get(p).copied().flatten());
+    let side = node.side_to_move;
+
+    let prunable = !pv_node && !in_check && beta.abs() < MATE_THRESHOLD;
+    let static_eval = if prunable { evaluate_relative(node, ctx.evaluator) } else { 0 };
+
+    if prunable
+        && ctx.options.reverse_futility
+        && depth <= REVERSE_FUTILITY_DEPTH
+        && static_eval - REVERSE_FUTILITY_MARGIN * depth as i64 >= beta
+    {
+        return static_eval;
+    }
+
+    // Null move: if passing still fails high, a real move almost certainly would too. Not
+    // twice in a row, and not without pieces, where having to move can be the problem.
+    if prunable
+        && ctx.options.null_move
+        && depth >= NULL_MOVE_MIN_DEPTH
+        && static_eval >= beta
+        && (ply == 0 || previous.is_some())
+        && node.has_non_pawn_material(side)
+    {
+        let reduction = if depth > 6 { 3 } else { 2 };
+        let null_hash = node.hash_after_null_move(hash, ctx.z);
+
+        if let Some(slot) = ctx.played.get_mut(ply) {
+            *slot = None;
+        }
+        let undo = node.make_null_move();
+        let score = -negamax_tt_pvs(node, depth - 1 - reduction, ply + 1, -beta, -beta + 1, null_hash, ctx);
+        node.unmake_null_move(undo);
+
+        if ctx.stopped {
+            return 0;
+        }
+        if score >= beta {
+            // Don't trust a mate found by passing.
+            return if score >= MATE_THRESHOLD { beta } else { score };
         }
     }
 
-    order_moves_soft_in_negamax(node, &mut moves);
+    let futility_margin = FUTILITY_MARGINS.get(depth as usize).copied();
+    let futile = prunable
+        && ctx.options.futility
+        && futility_margin.is_some_and(|margin| static_eval + margin <= alpha);
+    order_moves_soft_in_negamax(node, &mut moves, |m| ctx.history.quiet_score(side, m, ply, previous));
+
+    // The TT move goes first whatever kind of move it is.
+    if let Some(e) = ctx.tt.probe(hash)
+        && e.best_valid
+        && let Some(p) = moves.iter().position(|m| *m == e.best)
+    {
+        moves[..=p].rotate_right(1);
+    }
 
-    let mut best = -100_000_000;
+    let mut best = -INFINITY;
     let mut best_move = moves[0];
-    let mut first = true;
+    let mut searched = 0;
+    let mut quiets_tried: Vec<Move> = Vec::new();
 
     for mv in moves.iter() {
-        let child_hash = hash_after_move(hash, node, mv, side, z);
+        let child_hash = node.hash_after_move(hash, mv, ctx.z);
+        let quiet = !is_tactical(node, mv);
 
-        let mut tmp = *node;
-        make_move(&mut tmp, mv);
+        if let Some(slot) = ctx.played.get_mut(ply) {
+            *slot = Some(*mv);
+        }
+        let undo = node.make_move(mv);
+        let gives_check = node.is_in_check();
+
+        if futile && searched > 0 && quiet && !gives_check {
+            node.unmake_move(mv, undo);
+            // What the skipped move could have scored at most.
+            best = best.max(static_eval + futility_margin.unwrap_or(0));
+            continue;
+        }
 
-        let score = if first {
-            first = false;
-            -negamax_tt_pvs(&tmp, depth - 1, -beta, -alpha, opponent(side), child_hash, z, tt)
+        // Check extension: a check is searched one ply deeper, so forcing lines
+        // don't vanish over the horizon.
+        let new_depth = if gives_check { depth } else { depth - 1 };
+
+        let score = if searched == 0 {
+            -negamax_tt_pvs(node, new_depth, ply + 1, -beta, -alpha, child_hash, ctx)
         } else {
-            let mut s =
-                -negamax_tt_pvs(&tmp, depth - 1, -(alpha + 1), -alpha, opponent(side), child_hash, z, tt);
+            // Quiet moves this far down the ordering rarely matter: look at them shallower
+            // first and only search them properly if they turn out to beat alpha.
+            let reduction = if ctx.options.late_move_reductions
+                && quiet
+                && !in_check
+                && !gives_check
+                && depth >= LMR_MIN_DEPTH
+                && searched >= LMR_MIN_MOVES
+            {
+                if searched >= 2 * LMR_MIN_MOVES && depth >= 2 * LMR_MIN_DEPTH { 2 } else { 1 }
+            } else {
+                0
+            };
+
+            let mut s = if reduction > 0 {
+                -negamax_tt_pvs(node, new_depth - reduction, ply + 1, -(alpha + 1), -alpha, child_hash, ctx)
+            } else {
+                alpha + 1
+            };
+            if s > alpha {
+                s = -negamax_tt_pvs(node, new_depth, ply + 1, -(alpha + 1), -alpha, child_hash, ctx);
+            }
             if s > alpha && s < beta {
-                s = -negamax_tt_pvs(&tmp, depth - 1, -beta, -alpha, opponent(side), child_hash, z, tt);
+                s = -negamax_tt_pvs(node, new_depth, ply + 1, -beta, -alpha, child_hash, ctx);
             }
             s
         };
+        searched += 1;
+
+        node.unmake_move(mv, undo);
+
+        if ctx.stopped {
+            return 0;
+        }
 
         if score > best {
             best = score;
@@ -1183,10 +1887,17 @@ pub fn negamax_tt_pvs(
         }
         if score > alpha {
             alpha = score;
+            ctx.pv.update(ply, *mv);
         }
         if alpha >= beta {
+            if quiet {
+                ctx.history.on_cutoff(side, mv, depth, ply, previous, &quiets_tried);
+            }
             break;
         }
+        if quiet {
+            quiets_tried.push(*mv);
+        }
     }
 
     let flag = if best <= alpha_orig {
@@ -1197,10 +1908,10 @@ pub fn negamax_tt_pvs(
         TtFlag::Exact
     };
 
-    tt.store(TtEntry {
+    ctx.tt.store(TtEntry {
         key: hash,
         depth,
-        value: best,
+        value: score_to_tt(best, ply),
         flag,
         best: best_move,
         best_valid: true,
@@ -1209,71 +1920,343 @@ pub fn negamax_tt_pvs(
     best
 }
 
+/// Static evaluation from the side to move's point of view.
+#[inline]
+fn evaluate_relative<E: Evaluator>(node: &Position, evaluator: &E) -> i64 {
+    if node.side_to_move == Color::White {
+        evaluator.evaluate(node)
+    } else {
+        -evaluator.evaluate(node)
+    }
+}
+
+/// Plays out captures and promotions below the horizon until the position is quiet, so the
+/// static evaluation is never taken halfway through an exchange. In check every evasion is searched.
+pub fn quiescence<E: Evaluator>(node: &mut Position, ply: usize, mut alpha: i64, beta: i64, ctx: &mut SearchContext<E>) -> i64 {
+    if ctx.stopped() {
+        return 0;
+    }
+
+    // The PV ends where the quiescence search starts.
+    ctx.pv.clear(ply);
+
+    if ply >= MAX_PLY {
+        return evaluate_relative(node, ctx.evaluator);
+    }
+
+    ctx.count_node();
+
+    let in_check = node.is_in_check();
+    let mut moves = node.legal_moves();
+
+    if in_check && moves.is_empty() {
+        return -(MATE_SCORE - ply as i64);
+    }
+
+    let mut best = -INFINITY;
+
+    if !in_check {
+        // Stand pat: the side to move doesn't have to capture, so the static evaluation
+        // is a lower bound on what this node is worth.
+        let stand_pat = evaluate_relative(node, ctx.evaluator);
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+        best = stand_pat;
+
+        // Castling counts as tactical for ordering but doesn't change the material, and a
+        // capture that loses the exchange can't raise the score above standing pat.
+        moves.retain(|m| !m.castle && is_tactical(node, m) && see(node, m) >= 0);
+    }
+
+    order_moves_soft_in_negamax(node, &mut moves, |_| 0);
+
+    for mv in moves.iter() {
+        let undo = node.make_move(mv);
+        let score = -quiescence(node, ply + 1, -beta, -alpha, ctx);
+        node.unmake_move(mv, undo);
+
+        if ctx.stopped {
+            return 0;
+        }
+
+        if score > best {
+            best = score;
+        }
+        if score > alpha {
+            alpha = score;
+        }
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    best
+}
+
 // =========================
 // ROOT SEARCH (ITERATIVE)
 // =========================
 
+// Full moves until mate if `score` is a forced mate, negative when the side to move gets mated.
+fn mate_distance(score: i64) -> Option<i64> {
+    if score.abs() < MATE_THRESHOLD {
+        return None;
+    }
+    // Mate scores count down by one for every ply between the root and the mate.
+    let plies = MATE_SCORE - score.abs();
+    Some(if score > 0 { (plies + 1) / 2 } else { -(plies / 2) })
+}
+
+/// One root move's line in a MultiPV search.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PvLine {
+    pub best_move: Move,
+    /// From the side to move's point of view.
+    pub score: i64,
+    pub pv: Vec<Move>,
+}
+
+impl PvLine {
+    pub fn mate_in(&self) -> Option<i64> {
+        mate_distance(self.score)
+    }
+}
+
+/// Reported by the root after every completed iteration.
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: u8,
+    /// From the side to move's point of view.
+    pub score: i64,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    /// Permille of the transposition table in use.
+    pub hashfull: u32,
+    pub best_move: Move,
+    /// The line the engine expects, starting with `best_move`.
+    pub pv: Vec<Move>,
+    /// Best line first, then one per further root move up to the MultiPV count.
+    pub lines: Vec<PvLine>,
+}
+
+impl SearchInfo {
+    pub fn nps(&self) -> u64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { (self.nodes as f64 / secs) as u64 } else { 0 }
+    }
+
+    /// Full moves until mate if the score is a forced mate, negative when the side to move gets mated.
+    pub fn mate_in(&self) -> Option<i64> {
+        mate_distance(self.score)
+    }
+}
+
 pub fn get_best_move_iterative_tt(
-    node: &[[Piece; 8]; 8],
+    node: &Position,
+    limits: &SearchLimits,
+    stop: &AtomicBool,
+) -> Move {
+    search_iterative(node, limits, &SearchOptions::default(), stop, |_| {})
+}
+
+// Half-width of the first aspiration window, doubled on every fail.
+const ASPIRATION_WINDOW: i64 = 25;
+// Shallow iterations are cheap and their scores jump around too much to aim at.
+const ASPIRATION_MIN_DEPTH: u8 = 4;
+
+// One pass over the root moves to `depth`: the first with the full window, the rest with a
+// null window and only searched again if they beat it. Returns the best score, which lies
+// outside (alpha, beta) if the search failed low or high, and the move that got it.
+fn search_root<E: Evaluator>(
+    node: &mut Position,
+    moves: &[Move],
     depth: u8,
-    side: Color,
+    mut alpha: i64,
+    beta: i64,
+    hash: u64,
+    ctx: &mut SearchContext<E>,
+) -> (i64, Move) {
+    let mut best = -INFINITY;
+    let mut best_move = moves[0];
+    ctx.pv.clear(0);
+
+    for (i, mv) in moves.iter().enumerate() {
+        let child_hash = node.hash_after_move(hash, mv, ctx.z);
+        ctx.played[0] = Some(*mv);
+        let undo = node.make_move(mv);
+        let new_depth = if node.is_in_check() { depth } else { depth - 1 };
+
+        let score = if i == 0 {
+            -negamax_tt_pvs(node, new_depth, 1, -beta, -alpha, child_hash, ctx)
+        } else {
+            let mut s = -negamax_tt_pvs(node, new_depth, 1, -(alpha + 1), -alpha, child_hash, ctx);
+            if s > alpha && s < beta {
+                s = -negamax_tt_pvs(node, new_depth, 1, -beta, -alpha, child_hash, ctx);
+            }
+            s
+        };
+
+        node.unmake_move(mv, undo);
+
+        if ctx.stopped {
+            break;
+        }
+
+        if score > best {
+            best = score;
+            best_move = *mv;
+        }
+        if score > alpha {
+            alpha = score;
+            ctx.pv.update(0, *mv);
+        }
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    (best, best_move)
+}
+
+// Aspiration window: expect about the `expected` score and search a narrow window around it,
+// widening whichever side the result falls outside of until it fits. None if the search was
+// stopped before it did.
+fn aspiration_search<E: Evaluator>(
+    node: &mut Position,
+    moves: &[Move],
+    depth: u8,
+    expected: i64,
+    hash: u64,
+    ctx: &mut SearchContext<E>,
+) -> Option<(i64, Move)> {
+    let mut window = ASPIRATION_WINDOW;
+    let (mut alpha, mut beta) = if depth >= ASPIRATION_MIN_DEPTH && expected.abs() < MATE_THRESHOLD {
+        (expected - window, expected + window)
+    } else {
+        (-INFINITY, INFINITY)
+    };
+
+    loop {
+        let (value, best) = search_root(node, moves, depth, alpha, beta, hash, ctx);
+        if ctx.stopped {
+            return None;
+        }
+
+        window *= 2;
+        if value <= alpha {
+            alpha = (value - window).max(-INFINITY);
+        } else if value >= beta {
+            beta = (value + window).min(INFINITY);
+        } else {
+            return Some((value, best));
+        }
+    }
+}
+
+/// A search on an engine of its own, which starts out knowing nothing. See `Engine::search`.
+pub fn search_iterative(
+    node: &Position,
+    limits: &SearchLimits,
+    options: &SearchOptions,
+    stop: &AtomicBool,
+    on_iteration: impl FnMut(&SearchInfo),
 ) -> Move {
-    let z = Zobrist::new();
-    let mut tt = TranspositionTable::new_pow2(20);
+    Engine::default().search(node, limits, options, stop, on_iteration)
+}
 
-    let root_hash = zobrist_hash(node, side, &z);
-    let mut moves = get_all_legal_moves_for_this_turn(node, side);
+// A helper thread's iterative deepening: every other thread runs one ply deeper than the
+// main one, so the threads don't all finish the same iteration at once and reach the next
+// depth's positions early. Nothing is reported, the results only land in the table.
+fn helper_search<E: Evaluator>(mut node: Position, id: usize, max_depth: u8, ctx: &mut SearchContext<E>) {
+    let root_hash = node.hash(ctx.z);
+    let mut moves = node.legal_moves();
+    if moves.is_empty() {
+        return;
+    }
+    order_moves_soft_in_negamax(&node, &mut moves, |_| 0);
+
+    let offset = (id % 2) as u8;
+    let mut score = 0;
+    for d in 1..=max_depth.saturating_sub(offset) {
+        let Some((value, best)) = aspiration_search(&mut node, &moves, d + offset, score, root_hash, ctx) else {
+            return;
+        };
+        if let Some(p) = moves.iter().position(|m| *m == best) {
+            moves[..=p].rotate_right(1);
+        }
+        score = value;
+    }
+}
+
+fn iterative_deepening<E: Evaluator>(
+    mut node: Position,
+    limits: &SearchLimits,
+    ctx: &mut SearchContext<E>,
+    on_iteration: &mut impl FnMut(&SearchInfo),
+) -> Move {
+    let root_hash = node.hash(ctx.z);
+    let mut moves = node.legal_moves();
 
     if moves.is_empty() {
         return empty_move();
     }
 
-    order_moves_soft_in_negamax(node, &mut moves);
+    order_moves_soft_in_negamax(&node, &mut moves, |_| 0);
 
+    let multi_pv = ctx.options.multi_pv.clamp(1, moves.len());
     let mut best_move = moves[0];
+    // Every line's score in the previous iteration, to aim its aspiration window at.
+    let mut scores = vec![0; multi_pv];
+    let mut last_iteration = Duration::ZERO;
 
-    for d in 1..=depth {
-        let mut alpha = -100_000_000;
-        let beta = 100_000_000;
-
-        if let Some(e) = tt.probe(root_hash) {
-            if e.best_valid {
-                if let Some(p) = moves.iter().position(|m| *m == e.best) {
-                    moves.swap(0, p);
-                }
-            }
+    'deepening: for d in 1..=limits.depth {
+        if d > 1 && !ctx.time.start_iteration(last_iteration) {
+            break;
         }
+        let iteration_start = Instant::now();
+
+        // Each line searches the moves not taken by the lines before it, which are
+        // kept at the front in order. That order is also where the next iteration starts.
+        let mut lines = Vec::with_capacity(multi_pv);
+        for k in 0..multi_pv {
+            // A half-finished iteration may not have looked at the best move yet.
+            let Some((score, best)) = aspiration_search(&mut node, &moves[k..], d, scores[k], root_hash, ctx)
+            else {
+                break 'deepening;
+            };
 
-        for mv in moves.iter() {
-            let child_hash = hash_after_move(root_hash, node, mv, side, &z);
-            let mut tmp = *node;
-            make_move(&mut tmp, mv);
-
-            let score = -negamax_tt_pvs(
-                &tmp,
-                d.saturating_sub(1),
-                -beta,
-                -alpha,
-                opponent(side),
-                child_hash,
-                &z,
-                &mut tt,
-            );
-
-            if score > alpha {
-                alpha = score;
-                best_move = *mv;
+            if let Some(p) = moves[k..].iter().position(|m| *m == best) {
+                moves[k..=k + p].rotate_right(1);
             }
+            lines.push(PvLine { best_move: best, score, pv: ctx.pv.line(0).to_vec() });
         }
 
-        tt.store(TtEntry {
+        scores = lines.iter().map(|line| line.score).collect();
+        best_move = lines[0].best_move;
+
+        ctx.tt.store(TtEntry {
             key: root_hash,
             depth: d,
-            value: alpha,
+            value: lines[0].score,
             flag: TtFlag::Exact,
             best: best_move,
             best_valid: true,
         });
+
+        last_iteration = iteration_start.elapsed();
+
+        on_iteration(&SearchInfo {
+            depth: d,
+            score: lines[0].score,
+            nodes: ctx.total_nodes(),
+            elapsed: ctx.time.elapsed(),
+            hashfull: ctx.tt.hashfull(),
+            best_move,
+            pv: lines[0].pv.clone(),
+            lines,
+        });
     }
 
     best_move
@@ -1284,128 +2267,439 @@ pub fn get_best_move_iterative_tt(
 // White perspective (+ = good for White)
 // =========================
 
-#[inline(always)]
-fn piece_value(sym: Symbol) -> i64 {
-    match sym {
-        Symbol::Pawn   => 100,
-        Symbol::Knight => 320,
-        Symbol::Bishop => 330,
-        Symbol::Rook   => 500,
-        Symbol::Queen  => 900,
-        Symbol::King   => 0,   // king safety handled elsewhere
-        _ => 0,
+/// A static evaluation the search can be run with. Shared by all search threads, so it
+/// can't keep mutable state of its own other than through interior mutability.
+pub trait Evaluator: Send + Sync {
+    /// White perspective: positive = good for White
+    fn evaluate(&self, position: &Position) -> i64;
+}
+
+/// The built-in evaluation: material and piece-square tables, tapered by game phase, plus
+/// pawn structure, king safety and mobility.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HandcraftedEvaluator;
+
+impl Evaluator for HandcraftedEvaluator {
+    fn evaluate(&self, position: &Position) -> i64 {
+        evaluate_position(position)
     }
 }
 
-// Simple piece-square tables (from White's perspective)
-// Indexed as [row][col], row 0 = White back rank
-const PAWN_PST: [[i64; 8]; 8] = [
-    [  0,  0,  0,  0,  0,  0,  0,  0 ],
-    [ 50, 50, 50, 50, 50, 50, 50, 50 ],
-    [ 10, 10, 20, 30, 30, 20, 10, 10 ],
-    [  5,  5, 10, 25, 25, 10,  5,  5 ],
-    [  0,  0,  0, 20, 20,  0,  0,  0 ],
-    [  5, -5,-10,  0,  0,-10, -5,  5 ],
-    [  5, 10, 10,-20,-20, 10, 10,  5 ],
-    [  0,  0,  0,  0,  0,  0,  0,  0 ],
-];
+/// A midgame and an endgame value for the same thing, blended by the game phase.
+/// Saved as a `[mg, eg]` pair.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "[i64; 2]", into = "[i64; 2]")]
+pub struct Score {
+    pub mg: i64,
+    pub eg: i64,
+}
 
-const KNIGHT_PST: [[i64; 8]; 8] = [
-    [-50,-40,-30,-30,-30,-30,-40,-50],
-    [-40,-20,  0,  5,  5,  0,-20,-40],
-    [-30,  5, 10, 15, 15, 10,  5,-30],
-    [-30,  0, 15, 20, 20, 15,  0,-30],
-    [-30,  5, 15, 20, 20, 15,  5,-30],
-    [-30,  0, 10, 15, 15, 10,  0,-30],
-    [-40,-20,  0,  0,  0,  0,-20,-40],
-    [-50,-40,-30,-30,-30,-30,-40,-50],
-];
+impl Score {
+    pub const fn new(mg: i64, eg: i64) -> Self {
+        Self { mg, eg }
+    }
 
-const BISHOP_PST: [[i64; 8]; 8] = [
-    [-20,-10,-10,-10,-10,-10,-10,-20],
-    [-10,  0,  0,  0,  0,  0,  0,-10],
-    [-10,  0,  5, 10, 10,  5,  0,-10],
-    [-10,  5,  5, 10, 10,  5,  5,-10],
-    [-10,  0, 10, 10, 10, 10,  0,-10],
-    [-10, 10, 10, 10, 10, 10, 10,-10],
-    [-10,  5,  0,  0,  0,  0,  5,-10],
-    [-20,-10,-10,-10,-10,-10,-10,-20],
-];
+    /// The value at `phase`, from `MAX_PHASE` (all pieces on the board) down to 0 (only kings and pawns).
+    #[inline]
+    pub fn taper(self, phase: i64) -> i64 {
+        let phase = phase.min(MAX_PHASE);
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
 
-const ROOK_PST: [[i64; 8]; 8] = [
-    [  0,  0,  0,  5,  5,  0,  0,  0 ],
-    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
-    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
-    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
-    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
-    [ -5,  0,  0,  0,  0,  0,  0, -5 ],
-    [  5, 10, 10, 10, 10, 10, 10,  5 ],
-    [  0,  0,  0,  0,  0,  0,  0,  0 ],
-];
+impl From<[i64; 2]> for Score {
+    fn from([mg, eg]: [i64; 2]) -> Self {
+        Self::new(mg, eg)
+    }
+}
 
-const QUEEN_PST: [[i64; 8]; 8] = [
-    [-20,-10,-10, -5, -5,-10,-10,-20],
-    [-10,  0,  0,  0,  0,  0,  0,-10],
-    [-10,  0,  5,  5,  5,  5,  0,-10],
-    [ -5,  0,  5,  5,  5,  5,  0, -5],
-    [  0,  0,  5,  5,  5,  5,  0, -5],
-    [-10,  5,  5,  5,  5,  5,  0,-10],
-    [-10,  0,  5,  0,  0,  0,  0,-10],
-    [-20,-10,-10, -5, -5,-10,-10,-20],
-];
+impl From<Score> for [i64; 2] {
+    fn from(score: Score) -> Self {
+        [score.mg, score.eg]
+    }
+}
+
+impl ops::Add for Score {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl ops::Sub for Score {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl ops::Mul<i64> for Score {
+    type Output = Self;
+    fn mul(self, factor: i64) -> Self {
+        Self::new(self.mg * factor, self.eg * factor)
+    }
+}
+
+impl ops::AddAssign for Score {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl ops::SubAssign for Score {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+// Game phase: what each piece left on the board adds to it. The starting position has MAX_PHASE;
+// promotions can take it higher, which counts the same.
+const MAX_PHASE: i64 = 24;
 
 #[inline(always)]
-fn pst_bonus(sym: Symbol, row: usize, col: usize) -> i64 {
+fn phase_weight(sym: Symbol) -> i64 {
     match sym {
-        Symbol::Pawn   => PAWN_PST[row][col],
-        Symbol::Knight => KNIGHT_PST[row][col],
-        Symbol::Bishop => BISHOP_PST[row][col],
-        Symbol::Rook   => ROOK_PST[row][col],
-        Symbol::Queen  => QUEEN_PST[row][col],
+        Symbol::Knight | Symbol::Bishop => 1,
+        Symbol::Rook => 2,
+        Symbol::Queen => 4,
         _ => 0,
     }
 }
 
+#[inline(always)]
+fn pst_bonus(p: &EvalParams, sym: Symbol, row: usize, col: usize) -> Score {
+    p.tables(sym).map_or(Score::default(), |t| Score::new(t.mg[row][col], t.eg[row][col]))
+}
+
+#[inline(always)]
+fn piece_score(p: &EvalParams, sym: Symbol, color: Color, r: usize, c: usize) -> Score {
+    let pst = if color == Color::White {
+        pst_bonus(p, sym, r, c)
+    } else {
+        // mirror vertically for Black
+        pst_bonus(p, sym, 7 - r, c)
+    };
+
+    p.piece_value(sym) + pst
+}
+
 /// Main evaluation entry point
 /// White perspective: positive = good for White
 pub fn evaluate(board: &[[Piece; 8]; 8]) -> i64 {
-    let mut score: i64 = 0;
+    evaluate_position(&Position::from_board(*board, Color::White))
+}
 
-    for r in 0..8 {
-        for c in 0..8 {
-            let p = board[r][c];
-            if p.symbol == Symbol::Empty || p.color == Color::None {
-                continue;
+/// Same evaluation as `evaluate`, read straight off the position's bitboards.
+pub fn evaluate_position(position: &Position) -> i64 {
+    let p = eval_params::params();
+    let activity = piece_activity(position);
+    let mut score = pawn_structure(position) + activity.mobility + activity.king_safety;
+    let mut phase = 0;
+
+    for symbol in [Symbol::Pawn, Symbol::Knight, Symbol::Bishop, Symbol::Rook, Symbol::Queen, Symbol::King] {
+        for index in BitIter(position.pieces(Color::White, symbol)) {
+            let (r, c) = square_coords(index);
+            score += piece_score(p, symbol, Color::White, r as usize, c as usize);
+            phase += phase_weight(symbol);
+        }
+        for index in BitIter(position.pieces(Color::Black, symbol)) {
+            let (r, c) = square_coords(index);
+            score -= piece_score(p, symbol, Color::Black, r as usize, c as usize);
+            phase += phase_weight(symbol);
+        }
+    }
+
+    score.taper(phase)
+}
+
+// =========================
+// PAWN STRUCTURE
+// =========================
+
+// Entries per thread, as a power of two. Pawn structures change rarely, so this
+// is plenty to hit almost always.
+const PAWN_TABLE_BITS: usize = 14;
+
+// Rows ahead of a pawn of `color` on `row`, the way it moves.
+#[inline(always)]
+fn rows_ahead(color: Color, row: usize) -> Bitboard {
+    if color == Color::White {
+        (1u64 << (row * 8)) - 1
+    } else if row == 7 {
+        0
+    } else {
+        !0u64 << ((row + 1) * 8)
+    }
+}
+
+// The square in front of a pawn, None for one on the last row (only a FEN can put it there).
+#[inline(always)]
+fn pawn_stop(color: Color, index: usize) -> Option<usize> {
+    if color == Color::White { index.checked_sub(8) } else { Some(index + 8).filter(|&stop| stop < 64) }
+}
+
+#[inline(always)]
+fn relative_rank(color: Color, row: usize) -> usize {
+    if color == Color::White { 7 - row } else { row }
+}
+
+#[inline(always)]
+fn color_index(color: Color) -> usize {
+    if color == Color::White { 0 } else { 1 }
+}
+
+/// Everything about the pawns that only depends on the pawns, as cached.
+#[derive(Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    /// White perspective.
+    score: Score,
+    /// Each side's passed pawns, White first.
+    passed: [Bitboard; 2],
+}
+
+// The structure terms for one side's pawns, from that side's point of view.
+fn evaluate_pawns(p: &EvalParams, color: Color, own: Bitboard, enemy: Bitboard) -> (Score, Bitboard) {
+    let mut score = Score::default();
+    let mut passed = 0;
+
+    for col in 0..8 {
+        let on_file = (own & file_mask(col)).count_ones() as i64;
+        if on_file > 1 {
+            score += p.doubled_pawn * (on_file - 1);
+        }
+    }
+
+    for index in BitIter(own) {
+        let (row, col) = square_coords(index);
+        let (row, col) = (row as usize, col as usize);
+        let rank = relative_rank(color, row);
+        let ahead = rows_ahead(color, row);
+        let neighbours = own & adjacent_files(col);
+
+        // Only the front pawn of a doubled pair counts as passed, the one behind is just doubled.
+        if enemy & ahead & (file_mask(col) | adjacent_files(col)) == 0 && own & ahead & file_mask(col) == 0 {
+            score += p.passed_pawn[rank];
+            passed |= bit(index);
+        }
+
+        if neighbours == 0 {
+            score += p.isolated_pawn;
+        } else {
+            let behind = if color == Color::White { row + 1 } else { row.wrapping_sub(1) };
+            let supported = behind < 8 && neighbours & row_mask(behind) != 0;
+            if neighbours & row_mask(row) != 0 || supported {
+                score += p.connected_pawn[rank];
             }
 
-            let base = piece_value(p.symbol);
-            let pst = if p.color == Color::White {
-                pst_bonus(p.symbol, r, c)
-            } else {
-                // mirror vertically for Black
-                pst_bonus(p.symbol, 7 - r, c)
+            // No neighbour level with it or behind it can ever come up to support it.
+            if neighbours & !ahead == 0
+                && let Some(stop) = pawn_stop(color, index)
+                && pawn_attacks(color_index(color), stop) & enemy != 0
+            {
+                score += p.backward_pawn;
+            }
+        }
+    }
+
+    (score, passed)
+}
+
+fn compute_pawn_entry(p: &EvalParams, key: u64, white: Bitboard, black: Bitboard) -> PawnEntry {
+    let (white_score, white_passed) = evaluate_pawns(p, Color::White, white, black);
+    let (black_score, black_passed) = evaluate_pawns(p, Color::Black, black, white);
+    PawnEntry { key, score: white_score - black_score, passed: [white_passed, black_passed] }
+}
+
+struct PawnTable {
+    entries: Vec<PawnEntry>,
+}
+
+impl PawnTable {
+    fn new() -> Self {
+        Self { entries: vec![PawnEntry::default(); 1 << PAWN_TABLE_BITS] }
+    }
+
+    fn get(&mut self, p: &EvalParams, key: u64, white: Bitboard, black: Bitboard) -> PawnEntry {
+        let slot = &mut self.entries[(key as usize) & ((1 << PAWN_TABLE_BITS) - 1)];
+        // Key 0 means no pawns at all, which is never stored but cheap to work out.
+        if slot.key != key || key == 0 {
+            *slot = compute_pawn_entry(p, key, white, black);
+        }
+        *slot
+    }
+}
+
+thread_local! {
+    // One per thread, so search threads never wait on each other for it.
+    static PAWN_TABLE: RefCell<PawnTable> = RefCell::new(PawnTable::new());
+}
+
+fn pawn_zobrist() -> &'static Zobrist {
+    static KEYS: OnceLock<Zobrist> = OnceLock::new();
+    KEYS.get_or_init(Zobrist::new)
+}
+
+/// Pawn-structure terms, White perspective. What depends on the pawns alone comes out of the
+/// pawn hash table; whether a passed pawn is blocked depends on the other pieces too.
+pub fn pawn_structure(position: &Position) -> Score {
+    let p = eval_params::params();
+    let white = position.pieces(Color::White, Symbol::Pawn);
+    let black = position.pieces(Color::Black, Symbol::Pawn);
+    let key = position.pawn_hash(pawn_zobrist());
+    let entry = PAWN_TABLE.with(|table| table.borrow_mut().get(p, key, white, black));
+
+    let mut score = entry.score;
+    let occupied = position.occupied();
+    for (color, sign) in [(Color::White, 1), (Color::Black, -1)] {
+        for index in BitIter(entry.passed[color_index(color)]) {
+            if pawn_stop(color, index).is_some_and(|stop| occupied & bit(stop) != 0) {
+                let (row, _) = square_coords(index);
+                let bonus = p.passed_pawn[relative_rank(color, row as usize)];
+                let divisor = p.blocked_passed_pawn_divisor.max(1);
+                let lost = bonus - Score::new(bonus.mg / divisor, bonus.eg / divisor);
+                score -= lost * sign;
+            }
+        }
+    }
+    score
+}
+
+// =========================
+// KING SAFETY AND MOBILITY
+// =========================
+
+/// The terms that come from where pieces can go, White perspective.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Activity {
+    pub mobility: Score,
+    pub king_safety: Score,
+}
+
+/// Mobility of every knight, bishop, rook and queen, counting squares not held by own pieces or
+/// guarded by enemy pawns, and king safety: the pawn shield, open files next to the king and
+/// the weighted attacks of enemy pieces on the squares around it. Attacks come from the same
+/// tables the move generator uses.
+pub fn piece_activity(position: &Position) -> Activity {
+    let p = eval_params::params();
+    let mut activity = Activity::default();
+    for (color, sign) in [(Color::White, 1), (Color::Black, -1)] {
+        // What `color` gets out of sheltering its own king and threatening the other one.
+        let (mobility, pressure) = mobility_and_king_pressure(p, position, color);
+        activity.mobility += mobility * sign;
+        activity.king_safety += (king_shelter(p, position, color) + king_danger(p, pressure)) * sign;
+    }
+    activity
+}
+
+// Mobility of `color`'s pieces and the danger they put the enemy king in, as (weight, attackers).
+fn mobility_and_king_pressure(p: &EvalParams, position: &Position, color: Color) -> (Score, (i64, usize)) {
+    let enemy = opponent(color);
+    let occupied = position.occupied();
+    let own = position.occupied_by(color);
+
+    let mut guarded = 0;
+    for index in BitIter(position.pieces(enemy, Symbol::Pawn)) {
+        guarded |= pawn_attacks(color_index(enemy), index);
+    }
+
+    let enemy_king = position.pieces(enemy, Symbol::King);
+    let king_zone = if enemy_king == 0 {
+        0
+    } else {
+        let square = enemy_king.trailing_zeros() as usize;
+        king_attacks(square) | bit(square)
+    };
+
+    let mut mobility = Score::default();
+    let (mut weight, mut attackers) = (0, 0);
+    for symbol in [Symbol::Knight, Symbol::Bishop, Symbol::Rook, Symbol::Queen] {
+        let Mobility { per_square, typical } = p.mobility(symbol);
+        for index in BitIter(position.pieces(color, symbol)) {
+            let attacks = match symbol {
+                Symbol::Knight => knight_attacks(index),
+                Symbol::Bishop => bishop_attacks(index, occupied),
+                Symbol::Rook => rook_attacks(index, occupied),
+                _ => queen_attacks(index, occupied),
             };
 
-            let piece_score = base + pst;
+            mobility += per_square * ((attacks & !own & !guarded).count_ones() as i64 - typical);
 
-            if p.color == Color::White {
-                score += piece_score;
-            } else {
-                score -= piece_score;
+            let zone_attacks = (attacks & king_zone).count_ones() as i64;
+            if zone_attacks > 0 {
+                weight += p.king_attack(symbol) * zone_attacks;
+                attackers += 1;
             }
         }
     }
 
-    score
+    (mobility, (weight, attackers))
+}
+
+#[inline]
+fn king_danger(p: &EvalParams, (weight, attackers): (i64, usize)) -> Score {
+    let danger = weight * p.king_attackers_scale[attackers.min(7)] / 100;
+    Score::new(danger, danger / 4)
 }
 
+// Pawn shield and open files around `color`'s king, from its own point of view.
+fn king_shelter(p: &EvalParams, position: &Position, color: Color) -> Score {
+    let king = position.pieces(color, Symbol::King);
+    if king == 0 {
+        return Score::default();
+    }
+    let (row, col) = square_coords(king.trailing_zeros() as usize);
+    let own_pawns = position.pieces(color, Symbol::Pawn);
+    let all_pawns = own_pawns | position.pieces(opponent(color), Symbol::Pawn);
+
+    let mut score = Score::default();
+    for file in col.saturating_sub(1)..=(col + 1).min(7) {
+        let file = file_mask(file as usize);
+
+        for (distance, bonus) in p.shield_pawn.iter().enumerate() {
+            let shield_row = if color == Color::White {
+                (row as usize).checked_sub(distance + 1)
+            } else {
+                Some(row as usize + distance + 1).filter(|&r| r < 8)
+            };
+            if shield_row.is_some_and(|r| own_pawns & file & row_mask(r) != 0) {
+                score += *bonus;
+            }
+        }
+
+        if own_pawns & file == 0 {
+            score += p.semi_open_king_file;
+            if all_pawns & file == 0 {
+                score += p.open_king_file;
+            }
+        }
+    }
+    score
+}
 
 // *** AI GENERATED *** 
 
 
 
 // Communication
-//pub fn move_to_universal_chess_interface(move_: &Move) -> String {}
+pub fn move_to_universal_chess_interface(move_: &Move) -> String {
+    let mut output = String::with_capacity(5);
+
+    for square in [move_.current_square, move_.destination_square] {
+        output.push((b'a' + square.1) as char);
+        output.push((b'8' - square.0) as char);
+    }
+
+    match move_.promotion {
+        Promotion::Queen => output.push('q'),
+        Promotion::Rook => output.push('r'),
+        Promotion::Bishop => output.push('b'),
+        Promotion::Knight => output.push('n'),
+        Promotion::NoPromotion => {}
+    }
+
+    output
+}
 
 pub fn universal_chess_interface_to_move(board: &[[Piece;8];8], uci: String) -> Result<Move, &'static str> {
     
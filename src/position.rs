@@ -0,0 +1,1005 @@
+use std::fmt;
+
+use crate::bitboard::{self, BitIter, Bitboard, bit, square_coords, square_index};
+use crate::chess_board;
+use crate::pieces_logic::{self, Color, Move, Piece, Promotion, Symbol, Zobrist};
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CastlingRights {
+    pub white_kingside: bool,
+    pub white_queenside: bool,
+    pub black_kingside: bool,
+    pub black_queenside: bool,
+}
+
+impl CastlingRights {
+    pub fn none() -> Self {
+        Self {
+            white_kingside: false,
+            white_queenside: false,
+            black_kingside: false,
+            black_queenside: false,
+        }
+    }
+
+    /// Any move from or to one of these squares (king moving, rook moving or being captured)
+    /// gives up the matching rights for good.
+    fn remove_for_square(&mut self, square: (u8, u8)) {
+        match square {
+            (7, 4) => {
+                self.white_kingside = false;
+                self.white_queenside = false;
+            }
+            (7, 7) => self.white_kingside = false,
+            (7, 0) => self.white_queenside = false,
+            (0, 4) => {
+                self.black_kingside = false;
+                self.black_queenside = false;
+            }
+            (0, 7) => self.black_kingside = false,
+            (0, 0) => self.black_queenside = false,
+            _ => {}
+        }
+    }
+
+    pub fn allows(&self, side: Color, kingside: bool) -> bool {
+        match (side, kingside) {
+            (Color::White, true) => self.white_kingside,
+            (Color::White, false) => self.white_queenside,
+            (Color::Black, true) => self.black_kingside,
+            (Color::Black, false) => self.black_queenside,
+            _ => false,
+        }
+    }
+}
+
+const PIECE_KINDS: [Symbol; 6] = [
+    Symbol::King,
+    Symbol::Queen,
+    Symbol::Rook,
+    Symbol::Bishop,
+    Symbol::Knight,
+    Symbol::Pawn,
+];
+
+const PROMOTIONS: [Promotion; 4] = [Promotion::Queen, Promotion::Rook, Promotion::Bishop, Promotion::Knight];
+
+#[inline(always)]
+fn color_index(color: Color) -> usize {
+    if color == Color::White { 0 } else { 1 }
+}
+
+#[inline(always)]
+fn kind_index(symbol: Symbol) -> usize {
+    symbol as usize
+}
+
+/// The state make_move() throws away, kept so the move can be taken back without copying the position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Undo {
+    pub captured: Symbol,
+    pub castling: CastlingRights,
+    pub en_passant: Option<(u8, u8)>,
+    pub halfmove_clock: u32,
+    pub unmoved_pawns: Bitboard,
+}
+
+/// Everything needed to describe a game state, i.e. everything a FEN string carries.
+/// Pieces are kept as one bitboard per color and kind, plus a mailbox for square lookups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pieces: [[Bitboard; 6]; 2],
+    occupied: [Bitboard; 2],
+    mailbox: [Symbol; 64],
+    // Pawns that still have their first double step. Not always the ones on their start
+    // rank: Pawn Galore pawns can step up from the back rank onto it.
+    unmoved_pawns: Bitboard,
+    pub side_to_move: Color,
+    pub castling: CastlingRights,
+    pub en_passant: Option<(u8, u8)>,
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    WrongFieldCount(usize),
+    WrongRankCount(usize),
+    BadRankLength { rank: u8, squares: usize },
+    UnknownPiece { rank: u8, symbol: char },
+    WrongKingCount { color: Color, count: usize },
+    BadSideToMove(String),
+    BadCastling(String),
+    CastlingWithoutPieces(char),
+    BadEnPassant(String),
+    BadHalfmoveClock(String),
+    BadFullmoveNumber(String),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::WrongFieldCount(n) => write!(f, "expected 4 or 6 space separated fields, found {}", n),
+            FenError::WrongRankCount(n) => write!(f, "expected 8 ranks in the piece placement, found {}", n),
+            FenError::BadRankLength { rank, squares } => write!(f, "rank {} describes {} squares instead of 8", rank, squares),
+            FenError::UnknownPiece { rank, symbol } => write!(f, "unknown piece '{}' on rank {}", symbol, rank),
+            FenError::WrongKingCount { color, count } => write!(f, "expected exactly one {:?} king, found {}", color, count),
+            FenError::BadSideToMove(s) => write!(f, "side to move must be 'w' or 'b', found '{}'", s),
+            FenError::BadCastling(s) => write!(f, "castling rights must be '-' or a subset of 'KQkq', found '{}'", s),
+            FenError::CastlingWithoutPieces(c) => write!(f, "castling right '{}' given but king or rook is not on its home square", c),
+            FenError::BadEnPassant(s) => write!(f, "invalid en passant square '{}'", s),
+            FenError::BadHalfmoveClock(s) => write!(f, "invalid halfmove clock '{}'", s),
+            FenError::BadFullmoveNumber(s) => write!(f, "invalid fullmove number '{}'", s),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+/// "e4" -> (4, 4). Row 0 is the 8th rank, matching the board layout.
+pub fn square_from_algebraic(s: &str) -> Option<(u8, u8)> {
+    let bytes = s.as_bytes();
+    if bytes.len() != 2 || !(b'a'..=b'h').contains(&bytes[0]) || !(b'1'..=b'8').contains(&bytes[1]) {
+        return None;
+    }
+    Some((7 - (bytes[1] - b'1'), bytes[0] - b'a'))
+}
+
+/// (4, 4) -> "e4"
+pub fn square_to_algebraic(square: (u8, u8)) -> String {
+    format!("{}{}", (b'a' + square.1) as char, (b'8' - square.0) as char)
+}
+
+impl Position {
+    pub fn starting_position() -> Self {
+        Self::from_board(chess_board::initialize_chess_board(), Color::White)
+    }
+
+    /// Wraps a hand-built board (e.g. from chess_board) at the start of a game.
+    /// Castling rights are read off the has_moved flags of the kings and corner rooks,
+    /// and the pawns' double steps off their own.
+    pub fn from_board(board: [[Piece; 8]; 8], side_to_move: Color) -> Self {
+        let unmoved = |square: (usize, usize), symbol: Symbol, color: Color| {
+            let piece = board[square.0][square.1];
+            piece.symbol == symbol && piece.color == color && !piece.has_moved
+        };
+
+        let white_king = unmoved((7, 4), Symbol::King, Color::White);
+        let black_king = unmoved((0, 4), Symbol::King, Color::Black);
+
+        let castling = CastlingRights {
+            white_kingside: white_king && unmoved((7, 7), Symbol::Rook, Color::White),
+            white_queenside: white_king && unmoved((7, 0), Symbol::Rook, Color::White),
+            black_kingside: black_king && unmoved((0, 7), Symbol::Rook, Color::Black),
+            black_queenside: black_king && unmoved((0, 0), Symbol::Rook, Color::Black),
+        };
+
+        Self::from_parts(&board, side_to_move, castling, None, 0, 1)
+    }
+
+    fn from_parts(
+        board: &[[Piece; 8]; 8],
+        side_to_move: Color,
+        castling: CastlingRights,
+        en_passant: Option<(u8, u8)>,
+        halfmove_clock: u32,
+        fullmove_number: u32,
+    ) -> Self {
+        let mut position = Self {
+            pieces: [[0; 6]; 2],
+            occupied: [0; 2],
+            mailbox: [Symbol::Empty; 64],
+            unmoved_pawns: 0,
+            side_to_move,
+            castling,
+            en_passant,
+            halfmove_clock,
+            fullmove_number,
+        };
+
+        for (row, rank) in board.iter().enumerate() {
+            for (col, piece) in rank.iter().enumerate() {
+                if piece.symbol != Symbol::Empty && piece.color != Color::None {
+                    position.put_piece(row * 8 + col, color_index(piece.color), piece.symbol);
+                    if piece.symbol == Symbol::Pawn && !piece.has_moved {
+                        position.unmoved_pawns |= bit(row * 8 + col);
+                    }
+                }
+            }
+        }
+
+        position
+    }
+
+    /// The array layout used by the GUI and the per-piece move functions in pieces_logic.
+    pub fn board(&self) -> [[Piece; 8]; 8] {
+        let mut board = chess_board::create_empty_board();
+
+        for color in [Color::White, Color::Black] {
+            for symbol in PIECE_KINDS {
+                for index in BitIter(self.pieces(color, symbol)) {
+                    let square = square_coords(index);
+                    match symbol {
+                        Symbol::King => pieces_logic::place_king_on_board(&mut board, &square, color),
+                        Symbol::Queen => pieces_logic::place_queen_on_board(&mut board, &square, color),
+                        Symbol::Rook => pieces_logic::place_rook_on_board(&mut board, &square, color),
+                        Symbol::Bishop => pieces_logic::place_bishop_on_board(&mut board, &square, color),
+                        Symbol::Knight => pieces_logic::place_knight_on_board(&mut board, &square, color),
+                        Symbol::Pawn => pieces_logic::place_pawn_on_board(&mut board, &square, color),
+                        Symbol::Empty => {}
+                    }
+                }
+            }
+        }
+
+        sync_has_moved_flags(&mut board, &self.castling, self.unmoved_pawns);
+        board
+    }
+
+    #[inline(always)]
+    pub fn pieces(&self, color: Color, symbol: Symbol) -> Bitboard {
+        self.pieces[color_index(color)][kind_index(symbol)]
+    }
+
+    #[inline(always)]
+    pub fn occupied_by(&self, color: Color) -> Bitboard {
+        self.occupied[color_index(color)]
+    }
+
+    #[inline(always)]
+    pub fn occupied(&self) -> Bitboard {
+        self.occupied[0] | self.occupied[1]
+    }
+
+    #[inline(always)]
+    pub fn symbol_at(&self, square: (u8, u8)) -> Symbol {
+        self.mailbox[square_index(square)]
+    }
+
+    #[inline(always)]
+    pub fn color_at(&self, square: (u8, u8)) -> Color {
+        let b = bit(square_index(square));
+        if self.occupied[0] & b != 0 {
+            Color::White
+        } else if self.occupied[1] & b != 0 {
+            Color::Black
+        } else {
+            Color::None
+        }
+    }
+
+    #[inline(always)]
+    pub fn king_square(&self, color: Color) -> usize {
+        self.pieces(color, Symbol::King).trailing_zeros() as usize
+    }
+
+    #[inline(always)]
+    fn put_piece(&mut self, index: usize, color: usize, symbol: Symbol) {
+        self.pieces[color][kind_index(symbol)] |= bit(index);
+        self.occupied[color] |= bit(index);
+        self.mailbox[index] = symbol;
+    }
+
+    #[inline(always)]
+    fn remove_piece(&mut self, index: usize, color: usize, symbol: Symbol) {
+        self.pieces[color][kind_index(symbol)] &= !bit(index);
+        self.occupied[color] &= !bit(index);
+        self.mailbox[index] = Symbol::Empty;
+    }
+
+    // =========================
+    // ATTACKS
+    // =========================
+
+    /// Pieces of `by` (0 = White, 1 = Black) attacking `index`, given an occupancy for the sliders.
+    #[inline(always)]
+    fn attackers_by(&self, index: usize, by: usize, occupied: Bitboard) -> Bitboard {
+        let p = &self.pieces[by];
+        (bitboard::pawn_attacks(1 - by, index) & p[kind_index(Symbol::Pawn)])
+            | (bitboard::knight_attacks(index) & p[kind_index(Symbol::Knight)])
+            | (bitboard::king_attacks(index) & p[kind_index(Symbol::King)])
+            | (bitboard::bishop_attacks(index, occupied)
+                & (p[kind_index(Symbol::Bishop)] | p[kind_index(Symbol::Queen)]))
+            | (bitboard::rook_attacks(index, occupied)
+                & (p[kind_index(Symbol::Rook)] | p[kind_index(Symbol::Queen)]))
+    }
+
+    /// Pieces of both sides attacking `index`. Sliders see through whatever is missing from
+    /// `occupied`, which is how exchanges uncover x-ray attackers.
+    pub fn attackers_to(&self, index: usize, occupied: Bitboard) -> Bitboard {
+        (self.attackers_by(index, 0, occupied) | self.attackers_by(index, 1, occupied)) & occupied
+    }
+
+    pub fn is_square_attacked(&self, square: (u8, u8), by: Color) -> bool {
+        self.attackers_by(square_index(square), color_index(by), self.occupied()) != 0
+    }
+
+    pub fn is_in_check(&self) -> bool {
+        let us = color_index(self.side_to_move);
+        self.attackers_by(self.king_square(self.side_to_move), 1 - us, self.occupied()) != 0
+    }
+
+    // Own pieces that are the only thing between our king and an enemy slider.
+    fn pinned_pieces(&self, us: usize, king: usize) -> Bitboard {
+        let them = 1 - us;
+        let occupied = self.occupied();
+        let theirs = &self.pieces[them];
+        let queens = theirs[kind_index(Symbol::Queen)];
+
+        let snipers = (bitboard::rook_attacks(king, 0) & (theirs[kind_index(Symbol::Rook)] | queens))
+            | (bitboard::bishop_attacks(king, 0) & (theirs[kind_index(Symbol::Bishop)] | queens));
+
+        let mut pinned = 0;
+        for sniper in BitIter(snipers) {
+            let blockers = bitboard::between(king, sniper) & occupied;
+            if blockers.count_ones() == 1 {
+                pinned |= blockers & self.occupied[us];
+            }
+        }
+        pinned
+    }
+
+    // Plays the move out on the occupancy only and checks whether our king ends up attacked.
+    fn king_safe_after(&self, from: usize, to: usize, captured: Option<usize>) -> bool {
+        let us = color_index(self.side_to_move);
+        let them = 1 - us;
+
+        let mut occupied = (self.occupied() & !bit(from)) | bit(to);
+        let mut enemies = self.occupied[them];
+        if let Some(c) = captured {
+            enemies &= !bit(c);
+            if c != to {
+                occupied &= !bit(c);
+            }
+        }
+
+        let king = if self.mailbox[from] == Symbol::King {
+            to
+        } else {
+            self.king_square(self.side_to_move)
+        };
+
+        self.attackers_by(king, them, occupied) & enemies == 0
+    }
+
+    // =========================
+    // MOVE GENERATION
+    // =========================
+
+    #[inline(always)]
+    fn push_move(moves: &mut Vec<Move>, from: usize, to: usize, promotes: bool) {
+        let mv = Move {
+            current_square: square_coords(from),
+            destination_square: square_coords(to),
+            castle: false,
+            promotion: Promotion::NoPromotion,
+        };
+        if promotes {
+            for promotion in PROMOTIONS {
+                moves.push(Move { promotion, ..mv });
+            }
+        } else {
+            moves.push(mv);
+        }
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(48);
+
+        let side = self.side_to_move;
+        let us = color_index(side);
+        let them = 1 - us;
+        let occupied = self.occupied();
+        // The king itself is never a capture target.
+        let capturable = self.occupied[them] & !self.pieces[them][kind_index(Symbol::King)];
+        let targets = !occupied | capturable;
+
+        let king = self.king_square(side);
+        let checkers = self.attackers_by(king, them, occupied);
+        let pinned = self.pinned_pieces(us, king);
+
+        // King steps are always verified against the attacked squares.
+        for to in BitIter(bitboard::king_attacks(king) & targets) {
+            let captured = if capturable & bit(to) != 0 { Some(to) } else { None };
+            if self.king_safe_after(king, to, captured) {
+                Self::push_move(&mut moves, king, to, false);
+            }
+        }
+
+        // In double check only the king may move.
+        if checkers.count_ones() > 1 {
+            return moves;
+        }
+
+        // Everything but the king either needs to deal with the check or can go anywhere.
+        let evasion_mask = if checkers != 0 {
+            checkers | bitboard::between(king, checkers.trailing_zeros() as usize)
+        } else {
+            !0
+        };
+
+        let mut pseudo: Vec<(usize, usize, bool)> = Vec::with_capacity(48);
+
+        // Pawns
+        let (push, last_row): (isize, usize) = if side == Color::White { (-8, 0) } else { (8, 7) };
+        let empty = !occupied;
+
+        for from in BitIter(self.pieces[us][kind_index(Symbol::Pawn)]) {
+            let one = (from as isize + push) as usize;
+            if one < 64 && empty & bit(one) != 0 {
+                pseudo.push((from, one, one / 8 == last_row));
+
+                let two = one as isize + push;
+                if self.unmoved_pawns & bit(from) != 0 && (0..64).contains(&two) && empty & bit(two as usize) != 0 {
+                    pseudo.push((from, two as usize, false));
+                }
+            }
+
+            let attacks = bitboard::pawn_attacks(us, from);
+            for to in BitIter(attacks & capturable) {
+                pseudo.push((from, to, to / 8 == last_row));
+            }
+
+            if let Some(ep) = self.en_passant {
+                let to = square_index(ep);
+                if attacks & bit(to) != 0 {
+                    // Both pawns leave the rank at once, so always play it out in full.
+                    let captured = (from / 8) * 8 + to % 8;
+                    if self.mailbox[captured] == Symbol::Pawn
+                        && self.occupied[them] & bit(captured) != 0
+                        && self.king_safe_after(from, to, Some(captured))
+                    {
+                        Self::push_move(&mut moves, from, to, false);
+                    }
+                }
+            }
+        }
+
+        // Knights and sliders
+        for from in BitIter(self.pieces[us][kind_index(Symbol::Knight)]) {
+            for to in BitIter(bitboard::knight_attacks(from) & targets) {
+                pseudo.push((from, to, false));
+            }
+        }
+        for from in BitIter(self.pieces[us][kind_index(Symbol::Bishop)] | self.pieces[us][kind_index(Symbol::Queen)]) {
+            for to in BitIter(bitboard::bishop_attacks(from, occupied) & targets) {
+                pseudo.push((from, to, false));
+            }
+        }
+        for from in BitIter(self.pieces[us][kind_index(Symbol::Rook)] | self.pieces[us][kind_index(Symbol::Queen)]) {
+            for to in BitIter(bitboard::rook_attacks(from, occupied) & targets) {
+                pseudo.push((from, to, false));
+            }
+        }
+
+        for (from, to, promotes) in pseudo {
+            if evasion_mask & bit(to) == 0 {
+                continue;
+            }
+            if pinned & bit(from) != 0 {
+                let captured = if capturable & bit(to) != 0 { Some(to) } else { None };
+                if !self.king_safe_after(from, to, captured) {
+                    continue;
+                }
+            }
+            Self::push_move(&mut moves, from, to, promotes);
+        }
+
+        // Castling: not out of, through or into check
+        if checkers == 0 {
+            let row = if side == Color::White { 7 } else { 0 };
+            let rooks = self.pieces[us][kind_index(Symbol::Rook)];
+            let free = |cols: &[usize]| cols.iter().all(|c| occupied & bit(row * 8 + c) == 0);
+            let safe = |cols: &[usize]| cols.iter().all(|c| self.attackers_by(row * 8 + c, them, occupied) == 0);
+
+            if self.castling.allows(side, true) && rooks & bit(row * 8 + 7) != 0 && free(&[5, 6]) && safe(&[5, 6]) {
+                moves.push(Move {
+                    current_square: (row as u8, 4),
+                    destination_square: (row as u8, 6),
+                    castle: true,
+                    promotion: Promotion::NoPromotion,
+                });
+            }
+            if self.castling.allows(side, false) && rooks & bit(row * 8) != 0 && free(&[1, 2, 3]) && safe(&[3, 2]) {
+                moves.push(Move {
+                    current_square: (row as u8, 4),
+                    destination_square: (row as u8, 2),
+                    castle: true,
+                    promotion: Promotion::NoPromotion,
+                });
+            }
+        }
+
+        moves
+    }
+
+    pub fn legal_moves_from_square(&self, square: (u8, u8)) -> Vec<Move> {
+        let mut moves = self.legal_moves();
+        moves.retain(|m| m.current_square == square);
+        moves
+    }
+
+    // =========================
+    // MAKING MOVES
+    // =========================
+
+    #[inline(always)]
+    pub fn is_en_passant_capture(&self, mv: &Move) -> bool {
+        self.symbol_at(mv.current_square) == Symbol::Pawn
+            && mv.current_square.1 != mv.destination_square.1
+            && self.symbol_at(mv.destination_square) == Symbol::Empty
+    }
+
+    #[inline(always)]
+    pub fn is_capture(&self, mv: &Move) -> bool {
+        self.symbol_at(mv.destination_square) != Symbol::Empty || self.is_en_passant_capture(mv)
+    }
+
+    /// Plays a move produced by legal_moves() and hands the turn to the other side.
+    /// The returned record is what unmake_move() needs to take it back.
+    pub fn make_move(&mut self, mv: &Move) -> Undo {
+        let us = color_index(self.side_to_move);
+        let them = 1 - us;
+        let from = square_index(mv.current_square);
+        let to = square_index(mv.destination_square);
+
+        let moving = self.mailbox[from];
+        let captured = self.mailbox[to];
+
+        let undo = Undo {
+            captured: if self.is_en_passant_capture(mv) { Symbol::Pawn } else { captured },
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            unmoved_pawns: self.unmoved_pawns,
+        };
+
+        if moving == Symbol::Pawn || self.is_capture(mv) {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if self.side_to_move == Color::Black {
+            self.fullmove_number += 1;
+        }
+
+        // The captured pawn sits beside the moving pawn, not on the destination square.
+        if self.is_en_passant_capture(mv) {
+            self.remove_piece((from / 8) * 8 + to % 8, them, Symbol::Pawn);
+        }
+        if captured != Symbol::Empty {
+            self.remove_piece(to, them, captured);
+        }
+
+        self.remove_piece(from, us, moving);
+        self.put_piece(to, us, promoted_symbol(moving, mv.promotion));
+
+        if mv.castle {
+            let (rook_from, rook_to) = castle_rook_squares(from, to);
+            self.remove_piece(rook_from, us, Symbol::Rook);
+            self.put_piece(rook_to, us, Symbol::Rook);
+        }
+
+        self.en_passant = if moving == Symbol::Pawn && from.abs_diff(to) == 16 {
+            Some(square_coords((from + to) / 2))
+        } else {
+            None
+        };
+        self.castling.remove_for_square(mv.current_square);
+        self.castling.remove_for_square(mv.destination_square);
+        self.unmoved_pawns &= !(bit(from) | bit(to));
+
+        self.side_to_move = pieces_logic::opponent(self.side_to_move);
+
+        undo
+    }
+
+    /// Takes back `mv`, which must be the last move made on this position.
+    pub fn unmake_move(&mut self, mv: &Move, undo: Undo) {
+        self.side_to_move = pieces_logic::opponent(self.side_to_move);
+        if self.side_to_move == Color::Black {
+            self.fullmove_number -= 1;
+        }
+
+        let us = color_index(self.side_to_move);
+        let them = 1 - us;
+        let from = square_index(mv.current_square);
+        let to = square_index(mv.destination_square);
+
+        let landed = self.mailbox[to];
+        let moving = if mv.promotion == Promotion::NoPromotion { landed } else { Symbol::Pawn };
+
+        if mv.castle {
+            let (rook_from, rook_to) = castle_rook_squares(from, to);
+            self.remove_piece(rook_to, us, Symbol::Rook);
+            self.put_piece(rook_from, us, Symbol::Rook);
+        }
+
+        self.remove_piece(to, us, landed);
+        self.put_piece(from, us, moving);
+
+        if undo.captured != Symbol::Empty {
+            if moving == Symbol::Pawn && undo.en_passant == Some(mv.destination_square) {
+                self.put_piece((from / 8) * 8 + to % 8, them, Symbol::Pawn);
+            } else {
+                self.put_piece(to, them, undo.captured);
+            }
+        }
+
+        self.castling = undo.castling;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.unmoved_pawns = undo.unmoved_pawns;
+    }
+
+    /// Hands the turn to the other side without moving, for null-move pruning.
+    /// Must not be made while in check.
+    pub fn make_null_move(&mut self) -> Undo {
+        let undo = Undo {
+            captured: Symbol::Empty,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            unmoved_pawns: self.unmoved_pawns,
+        };
+
+        self.en_passant = None;
+        self.halfmove_clock += 1;
+        self.side_to_move = pieces_logic::opponent(self.side_to_move);
+
+        undo
+    }
+
+    pub fn unmake_null_move(&mut self, undo: Undo) {
+        self.side_to_move = pieces_logic::opponent(self.side_to_move);
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+    }
+
+    /// Whether `color` has anything besides king and pawns. Without it zugzwang is common.
+    pub fn has_non_pawn_material(&self, color: Color) -> bool {
+        self.occupied_by(color) & !(self.pieces(color, Symbol::Pawn) | self.pieces(color, Symbol::King)) != 0
+    }
+
+    pub fn is_checkmate(&self) -> bool {
+        self.is_in_check() && self.legal_moves().is_empty()
+    }
+
+    pub fn is_stalemate(&self) -> bool {
+        !self.is_in_check() && self.legal_moves().is_empty()
+    }
+
+    /// Same rule as pieces_logic::is_insufficient_material: a lone minor piece per side can't mate.
+    pub fn is_insufficient_material(&self) -> bool {
+        for color in [Color::White, Color::Black] {
+            if self.pieces(color, Symbol::Pawn) | self.pieces(color, Symbol::Rook) | self.pieces(color, Symbol::Queen) != 0 {
+                return false;
+            }
+        }
+        let minors = |color| (self.pieces(color, Symbol::Bishop) | self.pieces(color, Symbol::Knight)).count_ones();
+        minors(Color::White) < 2 && minors(Color::Black) < 2
+    }
+
+    /// White perspective: positive = good for White
+    pub fn evaluate(&self) -> i64 {
+        pieces_logic::evaluate_position(self)
+    }
+
+    // =========================
+    // HASHING
+    // =========================
+
+    /// Hash of the pawns alone, from the same keys as `hash`. Positions that only differ
+    /// in their other pieces share it.
+    pub fn pawn_hash(&self, z: &Zobrist) -> u64 {
+        let mut h = 0u64;
+        for color in [Color::White, Color::Black] {
+            for index in BitIter(self.pieces(color, Symbol::Pawn)) {
+                h ^= z.piece_key(index, color, Symbol::Pawn);
+            }
+        }
+        h
+    }
+
+    pub fn hash(&self, z: &Zobrist) -> u64 {
+        let mut h = 0u64;
+        for color in [Color::White, Color::Black] {
+            for symbol in PIECE_KINDS {
+                for index in BitIter(self.pieces(color, symbol)) {
+                    h ^= z.piece_key(index, color, symbol);
+                }
+            }
+        }
+        if self.side_to_move == Color::Black {
+            h ^= z.side_key();
+        }
+        if let Some(ep) = self.en_passant {
+            h ^= z.en_passant_key(ep.1);
+        }
+        h ^ z.castling_key(&self.castling)
+    }
+
+    /// Hash of the position after `mv`, without playing it.
+    pub fn hash_after_move(&self, mut h: u64, mv: &Move, z: &Zobrist) -> u64 {
+        let side = self.side_to_move;
+        let other = pieces_logic::opponent(side);
+        let from = square_index(mv.current_square);
+        let to = square_index(mv.destination_square);
+
+        let moving = self.mailbox[from];
+        let captured = self.mailbox[to];
+
+        h ^= z.piece_key(from, side, moving);
+        h ^= z.piece_key(to, side, promoted_symbol(moving, mv.promotion));
+
+        if captured != Symbol::Empty {
+            h ^= z.piece_key(to, other, captured);
+        }
+        if self.is_en_passant_capture(mv) {
+            h ^= z.piece_key((from / 8) * 8 + to % 8, other, Symbol::Pawn);
+        }
+        if mv.castle {
+            let (rook_from, rook_to) = castle_rook_squares(from, to);
+            h ^= z.piece_key(rook_from, side, Symbol::Rook) ^ z.piece_key(rook_to, side, Symbol::Rook);
+        }
+
+        if let Some(ep) = self.en_passant {
+            h ^= z.en_passant_key(ep.1);
+        }
+        if moving == Symbol::Pawn && from.abs_diff(to) == 16 {
+            h ^= z.en_passant_key(mv.current_square.1);
+        }
+
+        let mut castling = self.castling;
+        castling.remove_for_square(mv.current_square);
+        castling.remove_for_square(mv.destination_square);
+        h ^= z.castling_key(&self.castling) ^ z.castling_key(&castling);
+
+        h ^ z.side_key()
+    }
+
+    /// Hash of the position after a null move.
+    pub fn hash_after_null_move(&self, mut h: u64, z: &Zobrist) -> u64 {
+        if let Some(ep) = self.en_passant {
+            h ^= z.en_passant_key(ep.1);
+        }
+        h ^ z.side_key()
+    }
+
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() != 4 && fields.len() != 6 {
+            return Err(FenError::WrongFieldCount(fields.len()));
+        }
+
+        // Piece placement
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::WrongRankCount(ranks.len()));
+        }
+
+        let mut board = chess_board::create_empty_board();
+
+        for (row, rank) in ranks.iter().enumerate() {
+            let rank_number = 8 - row as u8;
+            let mut col: usize = 0;
+
+            for symbol in rank.chars() {
+                if let Some(skip) = symbol.to_digit(10) {
+                    if skip == 0 || skip > 8 {
+                        return Err(FenError::UnknownPiece { rank: rank_number, symbol });
+                    }
+                    col += skip as usize;
+                    continue;
+                }
+
+                if col >= 8 {
+                    return Err(FenError::BadRankLength { rank: rank_number, squares: col + 1 });
+                }
+
+                let color = if symbol.is_ascii_uppercase() { Color::White } else { Color::Black };
+                let square = (row as u8, col as u8);
+
+                match symbol.to_ascii_lowercase() {
+                    'p' => pieces_logic::place_pawn_on_board(&mut board, &square, color),
+                    'n' => pieces_logic::place_knight_on_board(&mut board, &square, color),
+                    'b' => pieces_logic::place_bishop_on_board(&mut board, &square, color),
+                    'r' => pieces_logic::place_rook_on_board(&mut board, &square, color),
+                    'q' => pieces_logic::place_queen_on_board(&mut board, &square, color),
+                    'k' => pieces_logic::place_king_on_board(&mut board, &square, color),
+                    _ => return Err(FenError::UnknownPiece { rank: rank_number, symbol }),
+                }
+                col += 1;
+            }
+
+            if col != 8 {
+                return Err(FenError::BadRankLength { rank: rank_number, squares: col });
+            }
+        }
+
+        for color in [Color::White, Color::Black] {
+            let count = board
+                .iter()
+                .flatten()
+                .filter(|p| p.symbol == Symbol::King && p.color == color)
+                .count();
+            if count != 1 {
+                return Err(FenError::WrongKingCount { color, count });
+            }
+        }
+
+        // Side to move
+        let side_to_move = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            other => return Err(FenError::BadSideToMove(other.to_string())),
+        };
+
+        // Castling rights
+        let mut castling = CastlingRights::none();
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                let right = match c {
+                    'K' => &mut castling.white_kingside,
+                    'Q' => &mut castling.white_queenside,
+                    'k' => &mut castling.black_kingside,
+                    'q' => &mut castling.black_queenside,
+                    _ => return Err(FenError::BadCastling(fields[2].to_string())),
+                };
+                if *right {
+                    return Err(FenError::BadCastling(fields[2].to_string()));
+                }
+                *right = true;
+
+                let (row, rook_col, color) = match c {
+                    'K' => (7, 7, Color::White),
+                    'Q' => (7, 0, Color::White),
+                    'k' => (0, 7, Color::Black),
+                    _ => (0, 0, Color::Black),
+                };
+                let king = board[row][4];
+                let rook = board[row][rook_col];
+                if king.symbol != Symbol::King || king.color != color
+                    || rook.symbol != Symbol::Rook || rook.color != color
+                {
+                    return Err(FenError::CastlingWithoutPieces(c));
+                }
+            }
+        }
+
+        // En passant
+        let en_passant = if fields[3] == "-" {
+            None
+        } else {
+            let square = square_from_algebraic(fields[3])
+                .ok_or_else(|| FenError::BadEnPassant(fields[3].to_string()))?;
+            // The pawn that just double stepped stands right beyond the square. Usually that puts
+            // it on the 6th or 3rd rank, but Pawn Galore pawns can double step off the back rank too.
+            let (row, col) = (square.0 as usize, square.1 as usize);
+            let (mover, beyond) = if side_to_move == Color::White {
+                (Color::Black, row + 1)
+            } else {
+                (Color::White, row.wrapping_sub(1))
+            };
+            let pawn_beyond = board.get(beyond)
+                .is_some_and(|rank| rank[col].symbol == Symbol::Pawn && rank[col].color == mover);
+            if board[row][col].symbol != Symbol::Empty || !pawn_beyond {
+                return Err(FenError::BadEnPassant(fields[3].to_string()));
+            }
+            Some(square)
+        };
+
+        // Move clocks (EPD style strings may leave them out)
+        let (halfmove_clock, fullmove_number) = if fields.len() == 6 {
+            let halfmove = fields[4]
+                .parse::<u32>()
+                .map_err(|_| FenError::BadHalfmoveClock(fields[4].to_string()))?;
+            let fullmove = fields[5]
+                .parse::<u32>()
+                .ok()
+                .filter(|n| *n >= 1)
+                .ok_or_else(|| FenError::BadFullmoveNumber(fields[5].to_string()))?;
+            (halfmove, fullmove)
+        } else {
+            (0, 1)
+        };
+
+        // FEN has no record of which pawns moved, so take every pawn on its start rank
+        // (or on the back rank, for Pawn Galore) to still have its double step.
+        let mut position = Self::from_parts(&board, side_to_move, castling, en_passant, halfmove_clock, fullmove_number);
+        position.unmoved_pawns = position.pieces(Color::White, Symbol::Pawn) & (0xFFFF_u64 << 48)
+            | position.pieces(Color::Black, Symbol::Pawn) & 0xFFFF;
+        Ok(position)
+    }
+
+    pub fn to_fen(self) -> String {
+        let mut placement = String::new();
+
+        for (row, rank) in self.board().iter().enumerate() {
+            let mut empty = 0;
+            for piece in rank {
+                if piece.symbol == Symbol::Empty {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    placement.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                placement.push(chess_board::get_char_symbol_for_symbol_enum(piece.symbol, piece.color == Color::White));
+            }
+            if empty > 0 {
+                placement.push_str(&empty.to_string());
+            }
+            if row < 7 {
+                placement.push('/');
+            }
+        }
+
+        let side = if self.side_to_move == Color::White { "w" } else { "b" };
+
+        let mut castling = String::new();
+        for (right, c) in [
+            (self.castling.white_kingside, 'K'),
+            (self.castling.white_queenside, 'Q'),
+            (self.castling.black_kingside, 'k'),
+            (self.castling.black_queenside, 'q'),
+        ] {
+            if right {
+                castling.push(c);
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let en_passant = match self.en_passant {
+            Some(square) => square_to_algebraic(square),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            placement, side, castling, en_passant, self.halfmove_clock, self.fullmove_number
+        )
+    }
+}
+
+#[inline(always)]
+fn promoted_symbol(moving: Symbol, promotion: Promotion) -> Symbol {
+    match promotion {
+        Promotion::Queen => Symbol::Queen,
+        Promotion::Rook => Symbol::Rook,
+        Promotion::Bishop => Symbol::Bishop,
+        Promotion::Knight => Symbol::Knight,
+        Promotion::NoPromotion => moving,
+    }
+}
+
+// King e -> g moves the h rook to f, king e -> c moves the a rook to d.
+#[inline(always)]
+fn castle_rook_squares(king_from: usize, king_to: usize) -> (usize, usize) {
+    let row = king_from / 8 * 8;
+    if king_to > king_from { (row + 7, row + 5) } else { (row, row + 3) }
+}
+
+// The array move generator reads castling rights and double pushes off Piece::has_moved,
+// so translate the castling rights and unmoved pawns into those flags.
+fn sync_has_moved_flags(board: &mut [[Piece; 8]; 8], castling: &CastlingRights, unmoved_pawns: Bitboard) {
+    for (row, rank) in board.iter_mut().enumerate() {
+        for (col, piece) in rank.iter_mut().enumerate() {
+            piece.has_moved = match (piece.symbol, piece.color) {
+                (Symbol::Pawn, _) => unmoved_pawns & bit(row * 8 + col) == 0,
+                (Symbol::King, Color::White) => !(castling.white_kingside || castling.white_queenside),
+                (Symbol::King, Color::Black) => !(castling.black_kingside || castling.black_queenside),
+                (Symbol::Rook, Color::White) => !((row, col) == (7, 7) && castling.white_kingside
+                    || (row, col) == (7, 0) && castling.white_queenside),
+                (Symbol::Rook, Color::Black) => !((row, col) == (0, 7) && castling.black_kingside
+                    || (row, col) == (0, 0) && castling.black_queenside),
+                _ => false,
+            };
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+/// Deepest iteration any search will run.
+pub const MAX_DEPTH: u8 = 64;
+
+// Kept back from the clock so communication lag never costs the game.
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+// How many more moves the clock has to last when the GUI doesn't say.
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+// A new iteration usually takes a few times longer than the one before it.
+const ITERATION_GROWTH: u32 = 3;
+
+/// Everything besides the stop flag that can end a search. Limits left at None don't apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub depth: u8,
+    pub nodes: Option<u64>,
+    /// Fixed time for this move, takes precedence over the clock.
+    pub movetime: Option<Duration>,
+    /// Time left on the side to move's clock.
+    pub time: Option<Duration>,
+    pub increment: Duration,
+    /// Moves until the next time control, None for sudden death.
+    pub moves_to_go: Option<u32>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            depth: MAX_DEPTH,
+            nodes: None,
+            movetime: None,
+            time: None,
+            increment: Duration::ZERO,
+            moves_to_go: None,
+        }
+    }
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u8) -> Self {
+        Self { depth, ..Self::default() }
+    }
+}
+
+/// Turns the limits of one search into deadlines, measured from when the search started.
+pub struct TimeManager {
+    start: Instant,
+    // What this move should take. No new iteration starts after it.
+    optimum: Option<Duration>,
+    // What this move may take at most. The running iteration is abandoned past it.
+    maximum: Option<Duration>,
+    nodes: Option<u64>,
+}
+
+impl TimeManager {
+    pub fn new(limits: &SearchLimits) -> Self {
+        let (optimum, maximum) = if let Some(movetime) = limits.movetime {
+            let time = movetime.saturating_sub(MOVE_OVERHEAD).max(movetime / 2);
+            (Some(time), Some(time))
+        } else if let Some(time) = limits.time {
+            let available = time.saturating_sub(MOVE_OVERHEAD).max(Duration::from_millis(1));
+            let moves_to_go = limits.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+            let share = time / moves_to_go + limits.increment * 3 / 4;
+
+            // Room to think longer when an iteration runs late, but never so much
+            // that the rest of the game is starved.
+            (Some(share.min(available)), Some((share * 4).min(available)))
+        } else {
+            (None, None)
+        };
+
+        Self { start: Instant::now(), optimum, maximum, nodes: limits.nodes }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn optimum(&self) -> Option<Duration> {
+        self.optimum
+    }
+
+    pub fn maximum(&self) -> Option<Duration> {
+        self.maximum
+    }
+
+    /// Whether the next iteration is worth starting, given how long the previous one took.
+    /// One that would most likely be cut off at the maximum only burns time.
+    pub fn start_iteration(&self, last_iteration: Duration) -> bool {
+        let elapsed = self.elapsed();
+        let in_optimum = self.optimum.is_none_or(|optimum| elapsed < optimum);
+        let fits = self.maximum.is_none_or(|maximum| elapsed + last_iteration * ITERATION_GROWTH <= maximum);
+        in_optimum && fits
+    }
+
+    /// Whether the running iteration has to be abandoned because time is up.
+    pub fn out_of_time(&self) -> bool {
+        self.maximum.is_some_and(|maximum| self.elapsed() >= maximum)
+    }
+
+    pub fn out_of_nodes(&self, nodes: u64) -> bool {
+        self.nodes.is_some_and(|limit| nodes >= limit)
+    }
+}
@@ -0,0 +1,362 @@
+use crate::pieces_logic::{self, Color, Engine, Evaluator, HandcraftedEvaluator, PvLine, SearchInfo, SearchOptions, DEFAULT_HASH_MB};
+use crate::position::Position;
+use crate::time_manager::{SearchLimits, MAX_DEPTH};
+
+use std::io::{self, BufRead};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, MutexGuard,
+};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The limits given on a `go` command. Times are in milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GoParams {
+    pub depth: Option<u8>,
+    pub movetime: Option<u64>,
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u64>,
+    pub nodes: Option<u64>,
+    pub infinite: bool,
+}
+
+impl GoParams {
+    /// Parses the arguments after `go`. Unknown tokens are skipped.
+    pub fn parse(args: &[&str]) -> Self {
+        let mut params = GoParams::default();
+        let mut tokens = args.iter();
+
+        while let Some(token) = tokens.next() {
+            let mut value = || tokens.next().copied();
+            match *token {
+                "depth" => params.depth = number(value()).map(|d| d.clamp(1, MAX_DEPTH as u64) as u8),
+                "movetime" => params.movetime = millis(value()),
+                "wtime" => params.wtime = millis(value()),
+                "btime" => params.btime = millis(value()),
+                "winc" => params.winc = millis(value()),
+                "binc" => params.binc = millis(value()),
+                "movestogo" => params.movestogo = number(value()),
+                "nodes" => params.nodes = number(value()),
+                "infinite" => params.infinite = true,
+                _ => {}
+            }
+        }
+
+        params
+    }
+
+    /// The search limits for `side`. `infinite` lifts everything but the depth,
+    /// leaving `stop` to end the search.
+    pub fn limits(&self, side: Color) -> SearchLimits {
+        let depth = self.depth.unwrap_or(MAX_DEPTH);
+        if self.infinite {
+            return SearchLimits::depth(depth);
+        }
+
+        let (time, inc) = if side == Color::White { (self.wtime, self.winc) } else { (self.btime, self.binc) };
+
+        SearchLimits {
+            depth,
+            nodes: self.nodes,
+            movetime: self.movetime.map(Duration::from_millis),
+            time: time.map(Duration::from_millis),
+            increment: Duration::from_millis(inc.unwrap_or(0)),
+            moves_to_go: self.movestogo.map(|m| m.min(u32::MAX as u64) as u32),
+        }
+    }
+}
+
+fn number(value: Option<&str>) -> Option<u64> {
+    value?.parse().ok()
+}
+
+// GUIs send negative clocks after lag or a flag fall. Those mean no time left, not no clock.
+fn millis(value: Option<&str>) -> Option<u64> {
+    value?.parse::<i64>().ok().map(|ms| ms.max(0) as u64)
+}
+
+/// Parses the arguments after `position`: `startpos` or `fen <fen>`, optionally followed by `moves ...`.
+pub fn parse_position(args: &[&str]) -> Result<Position, String> {
+    let moves_at = args.iter().position(|t| *t == "moves").unwrap_or(args.len());
+
+    let mut position = match args.first() {
+        Some(&"startpos") => Position::starting_position(),
+        Some(&"fen") => Position::from_fen(&args[1..moves_at].join(" ")).map_err(|e| e.to_string())?,
+        _ => return Err("expected startpos or fen".to_string()),
+    };
+
+    for token in args.iter().skip(moves_at + 1) {
+        let mv = position
+            .legal_moves()
+            .into_iter()
+            .find(|m| pieces_logic::move_to_universal_chess_interface(m) == *token)
+            .ok_or_else(|| format!("illegal move {}", token))?;
+        position.make_move(&mv);
+    }
+
+    Ok(position)
+}
+
+/// `cp <centipawns>`, or `mate <moves>` (negative when the side to move is getting mated).
+pub fn format_score(line: &PvLine) -> String {
+    match line.mate_in() {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", line.score),
+    }
+}
+
+/// The `info` lines for one iteration, one per PV. `multipv` is only given when there are several.
+pub fn format_info(info: &SearchInfo) -> String {
+    let lines: Vec<String> = info
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let multipv = if info.lines.len() > 1 { format!(" multipv {}", i + 1) } else { String::new() };
+            let pv: Vec<String> = line.pv.iter().map(pieces_logic::move_to_universal_chess_interface).collect();
+
+            format!(
+                "info depth {}{} score {} nodes {} nps {} hashfull {} time {} pv {}",
+                info.depth,
+                multipv,
+                format_score(line),
+                info.nodes,
+                info.nps(),
+                info.hashfull,
+                info.elapsed.as_millis(),
+                pv.join(" ")
+            )
+        })
+        .collect();
+
+    lines.join("\n")
+}
+
+// The `check` options GUIs can toggle, each switching one part of the search.
+const CHECK_OPTIONS: [&str; 4] = ["NullMove", "LateMoveReductions", "ReverseFutility", "Futility"];
+
+const MAX_MULTI_PV: usize = 64;
+const MAX_THREADS: usize = 256;
+const MAX_HASH_MB: usize = 65536;
+
+/// Parses the arguments after `setoption`: `name <id> [value <x>]`, where both can contain spaces.
+pub fn parse_setoption(args: &[&str]) -> Option<(String, Option<String>)> {
+    let (&"name", rest) = args.split_first()? else {
+        return None;
+    };
+    let value_at = rest.iter().position(|t| *t == "value");
+    let name = rest[..value_at.unwrap_or(rest.len())].join(" ");
+    let value = value_at.map(|i| rest[i + 1..].join(" "));
+
+    (!name.is_empty()).then_some((name, value))
+}
+
+/// Protocol state: the current position and the search running on it, if any. Generic over
+/// the evaluation, so different ones can play each other through the same front end.
+pub struct Uci<E = HandcraftedEvaluator> {
+    position: Position,
+    options: SearchOptions,
+    // Lent to the search thread while it runs, kept between searches.
+    engine: Arc<Mutex<Engine<E>>>,
+    stop: Arc<AtomicBool>,
+    search: Option<JoinHandle<()>>,
+}
+
+impl Default for Uci {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Uci {
+    pub fn new() -> Self {
+        Self::with_engine(Engine::default())
+    }
+}
+
+impl<E: Evaluator + 'static> Uci<E> {
+    pub fn with_engine(engine: Engine<E>) -> Self {
+        Self {
+            position: Position::starting_position(),
+            options: SearchOptions::default(),
+            engine: Arc::new(Mutex::new(engine)),
+            stop: Arc::new(AtomicBool::new(false)),
+            search: None,
+        }
+    }
+
+    /// Handles one line of input. Returns false once the GUI sent `quit`.
+    pub fn handle_command(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = tokens.split_first() else {
+            return true;
+        };
+
+        match *command {
+            "uci" => {
+                println!("id name Rockfish {}", env!("CARGO_PKG_VERSION"));
+                println!("id author the Rockfish developers");
+                for name in CHECK_OPTIONS {
+                    println!("option name {} type check default true", name);
+                }
+                println!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTI_PV);
+                println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+                println!("option name Hash type spin default {} min 1 max {}", DEFAULT_HASH_MB, MAX_HASH_MB);
+                println!("option name Clear Hash type button");
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                self.stop_search();
+                self.position = Position::starting_position();
+                self.engine().new_game();
+            }
+            "position" => {
+                self.stop_search();
+                match parse_position(args) {
+                    Ok(position) => self.position = position,
+                    Err(e) => println!("info string invalid position: {}", e),
+                }
+            }
+            "go" => {
+                self.stop_search();
+                self.go(GoParams::parse(args));
+            }
+            "stop" => self.stop_search(),
+            "quit" => {
+                self.stop_search();
+                return false;
+            }
+            "setoption" => {
+                self.stop_search();
+                self.set_option(args);
+            }
+            "debug" | "register" | "ponderhit" => {}
+            _ => println!("info string unknown command: {}", command),
+        }
+
+        true
+    }
+
+    fn set_option(&mut self, args: &[&str]) {
+        let Some((name, value)) = parse_setoption(args) else {
+            println!("info string malformed setoption");
+            return;
+        };
+
+        if name == "MultiPV" {
+            match value.and_then(|v| v.parse::<usize>().ok()) {
+                Some(n) if (1..=MAX_MULTI_PV).contains(&n) => self.options.multi_pv = n,
+                _ => println!("info string MultiPV needs a number from 1 to {}", MAX_MULTI_PV),
+            }
+            return;
+        }
+        if name == "Hash" {
+            match value.and_then(|v| v.parse::<usize>().ok()) {
+                Some(mb) if (1..=MAX_HASH_MB).contains(&mb) => self.engine().resize_hash(mb),
+                _ => println!("info string Hash needs a number of megabytes from 1 to {}", MAX_HASH_MB),
+            }
+            return;
+        }
+        if name == "Clear Hash" {
+            self.engine().new_game();
+            return;
+        }
+        if name == "Threads" {
+            match value.and_then(|v| v.parse::<usize>().ok()) {
+                Some(n) if (1..=MAX_THREADS).contains(&n) => self.options.threads = n,
+                _ => println!("info string Threads needs a number from 1 to {}", MAX_THREADS),
+            }
+            return;
+        }
+
+        let on = match value.as_deref() {
+            Some("true") => true,
+            Some("false") => false,
+            _ => {
+                println!("info string option {} needs true or false", name);
+                return;
+            }
+        };
+
+        match name.as_str() {
+            "NullMove" => self.options.null_move = on,
+            "LateMoveReductions" => self.options.late_move_reductions = on,
+            "ReverseFutility" => self.options.reverse_futility = on,
+            "Futility" => self.options.futility = on,
+            _ => println!("info string unknown option {}", name),
+        }
+    }
+
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+
+    /// The engine between searches. Waits for one that is still running.
+    pub fn engine(&mut self) -> MutexGuard<'_, Engine<E>> {
+        self.wait_for_search();
+        self.engine.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn go(&mut self, params: GoParams) {
+        let position = self.position;
+        let limits = params.limits(position.side_to_move);
+        let options = self.options;
+
+        self.stop.store(false, Ordering::Relaxed);
+        let stop = Arc::clone(&self.stop);
+        let engine = Arc::clone(&self.engine);
+
+        self.search = Some(thread::spawn(move || {
+            let mut engine = engine.lock().unwrap_or_else(|e| e.into_inner());
+            let best = engine.search(&position, &limits, &options, &stop, |info| println!("{}", format_info(info)));
+            drop(engine);
+
+            // In infinite mode the answer has to wait for `stop`.
+            if params.infinite {
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+
+            if position.legal_moves().is_empty() {
+                println!("bestmove 0000");
+            } else {
+                println!("bestmove {}", pieces_logic::move_to_universal_chess_interface(&best));
+            }
+        }));
+    }
+
+    /// Tells a running search to finish and waits for its `bestmove`.
+    // GUIs shouldn't send a new position or `go` while searching, but if one does, it ends
+    // the search like `stop` would: this loop is the only reader of stdin, so waiting for
+    // an infinite search to finish on its own would never see the `stop` that ends it.
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.wait_for_search();
+    }
+
+    fn wait_for_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            let _ = search.join();
+        }
+    }
+}
+
+/// Reads commands from stdin until `quit` or end of input.
+pub fn run() {
+    let mut uci = Uci::new();
+
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        if !uci.handle_command(&line) {
+            return;
+        }
+    }
+
+    uci.stop_search();
+}
@@ -0,0 +1,390 @@
+use crate::pieces_logic::{self, Color, Engine, Move, SearchInfo, SearchOptions};
+use crate::position::{Position, Undo};
+use crate::time_manager::{SearchLimits, MAX_DEPTH};
+
+use std::io::{self, BufRead};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc, Mutex,
+};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// xboard's convention for mate scores in thinking output: 100000 + moves to mate.
+const XBOARD_MATE: i64 = 100_000;
+
+/// A `level` command: `moves` per session (0 = whole game), base time and increment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeControl {
+    pub moves: u32,
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    /// Parses `level MPS BASE INC`, where BASE is minutes or `minutes:seconds`
+    /// and INC is seconds.
+    pub fn parse(args: &[&str]) -> Option<Self> {
+        let [moves, base, increment] = args else {
+            return None;
+        };
+
+        let base = match base.split_once(':') {
+            Some((min, sec)) => min.parse::<u64>().ok()? * 60 + sec.parse::<u64>().ok()?,
+            None => base.parse::<u64>().ok()? * 60,
+        };
+
+        Some(Self {
+            moves: moves.parse().ok()?,
+            base: Duration::from_secs(base),
+            increment: Duration::try_from_secs_f64(increment.parse().ok()?).ok()?,
+        })
+    }
+}
+
+/// The result line xboard expects once the game is over, or None while it goes on.
+pub fn game_result(position: &Position) -> Option<&'static str> {
+    if position.is_checkmate() {
+        return Some(if position.side_to_move == Color::White {
+            "0-1 {Black mates}"
+        } else {
+            "1-0 {White mates}"
+        });
+    }
+    if position.is_stalemate() {
+        return Some("1/2-1/2 {Stalemate}");
+    }
+    if position.is_insufficient_material() {
+        return Some("1/2-1/2 {Insufficient material}");
+    }
+    if position.halfmove_clock >= 100 {
+        return Some("1/2-1/2 {Fifty move rule}");
+    }
+    None
+}
+
+/// One line of `post` output: `ply score time nodes pv`, time in centiseconds.
+pub fn format_thinking(info: &SearchInfo) -> String {
+    let score = match info.mate_in() {
+        Some(moves) if moves > 0 => XBOARD_MATE + moves,
+        Some(moves) => -XBOARD_MATE + moves,
+        None => info.score,
+    };
+    let pv: Vec<String> = info.pv.iter().map(pieces_logic::move_to_universal_chess_interface).collect();
+
+    format!("{} {} {} {} {}", info.depth, score, info.elapsed.as_millis() / 10, info.nodes, pv.join(" "))
+}
+
+/// Everything the main loop reacts to: lines from the GUI and moves from the search thread.
+pub enum Event {
+    Command(String),
+    BestMove { search_id: u64, best: Move },
+}
+
+/// Protocol state for one xboard session.
+pub struct XBoard {
+    position: Position,
+    history: Vec<(Move, Undo)>,
+    // None while in force mode.
+    engine_side: Option<Color>,
+    time_control: TimeControl,
+    // Fixed time per move from `st`, replaces the time control.
+    move_time: Option<Duration>,
+    max_depth: u8,
+    // Search threads, set by `cores`.
+    threads: usize,
+    // Lent to the search thread while it runs, kept between searches.
+    engine: Arc<Mutex<Engine>>,
+    engine_clock: Option<Duration>,
+    post: bool,
+    game_over: bool,
+    events: mpsc::Sender<Event>,
+    stop: Arc<AtomicBool>,
+    search: Option<JoinHandle<()>>,
+    search_id: u64,
+}
+
+impl XBoard {
+    pub fn new(events: mpsc::Sender<Event>) -> Self {
+        Self {
+            position: Position::starting_position(),
+            history: Vec::new(),
+            engine_side: Some(Color::Black),
+            time_control: TimeControl { moves: 40, base: Duration::from_secs(300), increment: Duration::ZERO },
+            move_time: None,
+            max_depth: MAX_DEPTH,
+            threads: 1,
+            engine: Arc::new(Mutex::new(Engine::default())),
+            engine_clock: None,
+            post: false,
+            game_over: false,
+            events,
+            stop: Arc::new(AtomicBool::new(false)),
+            search: None,
+            search_id: 0,
+        }
+    }
+
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// Handles one line from the GUI. Returns false once it sent `quit`.
+    pub fn handle_command(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = tokens.split_first() else {
+            return true;
+        };
+
+        match *command {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "otim" | "name"
+            | "rating" | "ics" => {}
+            "protover" => {
+                println!(
+                    "feature myname=\"Rockfish {}\" usermove=1 setboard=1 ping=1 playother=1 colors=0 sigint=0 sigterm=0 analyze=0 smp=1 memory=1 done=1",
+                    env!("CARGO_PKG_VERSION")
+                );
+            }
+            "new" => {
+                self.cancel_search();
+                self.position = Position::starting_position();
+                self.history.clear();
+                self.engine_side = Some(Color::Black);
+                self.move_time = None;
+                self.max_depth = MAX_DEPTH;
+                self.engine_clock = None;
+                self.game_over = false;
+                self.engine.lock().unwrap_or_else(|e| e.into_inner()).new_game();
+            }
+            "force" => {
+                self.cancel_search();
+                self.engine_side = None;
+            }
+            "go" => {
+                self.cancel_search();
+                self.engine_side = Some(self.position.side_to_move);
+                self.think();
+            }
+            "playother" => {
+                self.cancel_search();
+                self.engine_side = Some(pieces_logic::opponent(self.position.side_to_move));
+            }
+            "usermove" => match args.first() {
+                Some(mv) => self.user_move(mv),
+                None => println!("Error (missing move): usermove"),
+            },
+            "level" => match TimeControl::parse(args) {
+                Some(tc) => {
+                    self.time_control = tc;
+                    self.move_time = None;
+                }
+                None => println!("Error (bad time control): {}", line.trim()),
+            },
+            "st" => match args.first().and_then(|s| Duration::try_from_secs_f64(s.parse().ok()?).ok()) {
+                Some(time) if !time.is_zero() => self.move_time = Some(time),
+                _ => println!("Error (bad time): {}", line.trim()),
+            },
+            "sd" => match args.first().and_then(|d| d.parse::<u8>().ok()) {
+                Some(depth) if depth > 0 => self.max_depth = depth.min(MAX_DEPTH),
+                _ => println!("Error (bad depth): {}", line.trim()),
+            },
+            "memory" => match args.first().and_then(|mb| mb.parse::<usize>().ok()) {
+                Some(mb) if mb > 0 => {
+                    self.cancel_search();
+                    self.engine.lock().unwrap_or_else(|e| e.into_inner()).resize_hash(mb);
+                }
+                _ => println!("Error (bad memory size): {}", line.trim()),
+            },
+            "cores" => match args.first().and_then(|n| n.parse::<usize>().ok()) {
+                Some(threads) if threads > 0 => self.threads = threads,
+                _ => println!("Error (bad number of cores): {}", line.trim()),
+            },
+            "time" => {
+                if let Some(cs) = args.first().and_then(|t| t.parse::<u64>().ok()) {
+                    self.engine_clock = Some(Duration::from_millis(cs * 10));
+                }
+            }
+            "undo" => {
+                self.cancel_search();
+                self.take_back(1);
+            }
+            "remove" => {
+                self.cancel_search();
+                self.take_back(2);
+            }
+            "result" => {
+                self.cancel_search();
+                self.game_over = true;
+            }
+            "setboard" => {
+                self.cancel_search();
+                match Position::from_fen(&args.join(" ")) {
+                    Ok(position) => {
+                        self.position = position;
+                        self.history.clear();
+                        self.game_over = false;
+                    }
+                    Err(e) => println!("tellusererror Illegal position: {}", e),
+                }
+            }
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "ping" => println!("pong {}", args.first().unwrap_or(&"")),
+            // Move now: the search returns what it has after the current iteration.
+            "?" => self.stop.store(true, Ordering::Relaxed),
+            "quit" => {
+                self.cancel_search();
+                return false;
+            }
+            _ => {
+                // Without usermove=1 older interfaces send bare moves.
+                if self.find_move(command).is_some() {
+                    self.user_move(command);
+                } else {
+                    println!("Error (unknown command): {}", command);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Called by the main loop when a search thread reports its move.
+    pub fn handle_best_move(&mut self, search_id: u64, best: Move) {
+        // A result from a search that was cancelled in the meantime.
+        if search_id != self.search_id {
+            return;
+        }
+        if let Some(search) = self.search.take() {
+            let _ = search.join();
+        }
+
+        println!("move {}", pieces_logic::move_to_universal_chess_interface(&best));
+        self.play(best);
+    }
+
+    fn find_move(&self, text: &str) -> Option<Move> {
+        self.position
+            .legal_moves()
+            .into_iter()
+            .find(|m| pieces_logic::move_to_universal_chess_interface(m) == text)
+    }
+
+    fn user_move(&mut self, text: &str) {
+        let Some(mv) = self.find_move(text) else {
+            println!("Illegal move: {}", text);
+            return;
+        };
+
+        self.cancel_search();
+        self.play(mv);
+
+        if self.engine_side == Some(self.position.side_to_move) {
+            self.think();
+        }
+    }
+
+    fn play(&mut self, mv: Move) {
+        let undo = self.position.make_move(&mv);
+        self.history.push((mv, undo));
+
+        if let Some(result) = game_result(&self.position) {
+            println!("{}", result);
+            self.game_over = true;
+        }
+    }
+
+    fn take_back(&mut self, moves: usize) {
+        for _ in 0..moves {
+            let Some((mv, undo)) = self.history.pop() else { break };
+            self.position.unmake_move(&mv, undo);
+        }
+        self.game_over = game_result(&self.position).is_some();
+    }
+
+    fn limits(&self) -> SearchLimits {
+        let moves_to_go = if self.time_control.moves > 0 {
+            let played = self.history.len() as u32 / 2;
+            Some(self.time_control.moves - played % self.time_control.moves)
+        } else {
+            None
+        };
+
+        SearchLimits {
+            depth: self.max_depth,
+            movetime: self.move_time,
+            time: Some(self.engine_clock.unwrap_or(self.time_control.base)),
+            increment: self.time_control.increment,
+            moves_to_go,
+            ..SearchLimits::default()
+        }
+    }
+
+    fn think(&mut self) {
+        if self.game_over || self.position.legal_moves().is_empty() {
+            return;
+        }
+
+        let position = self.position;
+        let limits = self.limits();
+        let options = SearchOptions { threads: self.threads, ..SearchOptions::default() };
+        let post = self.post;
+
+        self.search_id += 1;
+        let search_id = self.search_id;
+
+        self.stop.store(false, Ordering::Relaxed);
+        let stop = Arc::clone(&self.stop);
+        let events = self.events.clone();
+        let engine = Arc::clone(&self.engine);
+
+        self.search = Some(thread::spawn(move || {
+            let mut engine = engine.lock().unwrap_or_else(|e| e.into_inner());
+            let best = engine.search(&position, &limits, &options, &stop, |info| {
+                if post {
+                    println!("{}", format_thinking(info));
+                }
+            });
+
+            let _ = events.send(Event::BestMove { search_id, best });
+        }));
+    }
+
+    // Stops a running search and makes sure its move is never played.
+    fn cancel_search(&mut self) {
+        self.search_id += 1;
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(search) = self.search.take() {
+            let _ = search.join();
+        }
+    }
+}
+
+/// Reads commands from stdin until `quit` or end of input.
+pub fn run() {
+    let (tx, rx) = mpsc::channel();
+
+    let input = tx.clone();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if input.send(Event::Command(line)).is_err() {
+                break;
+            }
+        }
+        let _ = input.send(Event::Command("quit".to_string()));
+    });
+
+    let mut xboard = XBoard::new(tx);
+
+    for event in rx {
+        match event {
+            Event::Command(line) => {
+                if !xboard.handle_command(&line) {
+                    return;
+                }
+            }
+            Event::BestMove { search_id, best } => xboard.handle_best_move(search_id, best),
+        }
+    }
+}